@@ -0,0 +1,65 @@
+//! Streaming uploads from disk to a client, like views of uploads
+//! that aren't cached do.
+//!
+//! Run with `cargo bench --bench disk_stream`. Each buffer size is how much
+//! is read at a time (`disk.read_buffer`). 4KiB is `ReaderStream`'s default.
+
+use std::path::PathBuf;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use tokio::{fs::File, runtime::Runtime};
+use tokio_stream::StreamExt;
+use tokio_util::io::ReaderStream;
+
+/// Upload sizes to stream
+const SIZES: &[usize] = &[1024 * 1024, 64 * 1024 * 1024];
+
+/// Read buffer sizes to compare
+const BUFFERS: &[usize] = &[4 * 1024, 64 * 1024, 256 * 1024, 1024 * 1024];
+
+/// Write a file of the given size to stream back
+fn make_file(len: usize) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("breeze-bench-{}-{len}", std::process::id()));
+    let data: Vec<u8> = (0..len).map(|n| n as u8).collect();
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+/// Stream a whole file, like a view response body is
+async fn stream(path: &PathBuf, buffer: usize) -> usize {
+    let f = File::open(path).await.unwrap();
+    let mut stream = ReaderStream::with_capacity(f, buffer);
+
+    let mut total = 0;
+    while let Some(chunk) = stream.next().await {
+        total += chunk.unwrap().len();
+    }
+    total
+}
+
+fn disk_stream(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("disk_stream");
+    group.sample_size(20);
+
+    for &len in SIZES {
+        let path = make_file(len);
+        group.throughput(Throughput::Bytes(len as u64));
+
+        for &buffer in BUFFERS {
+            let id = BenchmarkId::new(format!("{}KiB", buffer / 1024), len);
+            group.bench_with_input(id, &path, |b, path| {
+                b.to_async(&rt).iter(|| async move {
+                    assert_eq!(stream(path, buffer).await, len);
+                });
+            });
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, disk_stream);
+criterion_main!(benches);
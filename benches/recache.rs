@@ -0,0 +1,113 @@
+//! Reading uploads from disk into memory, like the cache does when
+//! an upload is viewed that isn't cached yet.
+//!
+//! Run with `cargo bench --bench recache`. The old `read_buf` loop is
+//! measured alongside, to compare against.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bytes::{Bytes, BytesMut};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use tokio::{fs::File, io::AsyncReadExt, runtime::Runtime};
+
+#[path = "../src/read.rs"]
+mod read;
+
+/// Keeps track of how much memory is allocated at most,
+/// so reads can be checked for over-allocating
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let now = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(now, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Upload sizes to read
+const SIZES: &[usize] = &[64 * 1024, 1024 * 1024, 16 * 1024 * 1024, 64 * 1024 * 1024];
+
+/// How much more than the upload itself a read may allocate
+const ALLOC_SLACK: usize = 256 * 1024;
+
+/// The old way: `read_buf` into a buffer sized up front, until it runs out
+async fn read_buf_loop(mut f: File, len: u64) -> Bytes {
+    let mut data = BytesMut::with_capacity(len as usize);
+    while f.read_buf(&mut data).await.unwrap() != 0 {}
+    data.freeze()
+}
+
+/// Write a file of the given size to read back
+fn make_file(len: usize) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("breeze-bench-{}-{len}", std::process::id()));
+    let data: Vec<u8> = (0..len).map(|n| n as u8).collect();
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+/// Make sure reading a file doesn't take much more memory than the file
+fn check_peak_memory(rt: &Runtime, path: &PathBuf, len: usize) {
+    let data = rt.block_on(async {
+        let f = File::open(path).await.unwrap();
+        let before = ALLOCATED.load(Ordering::Relaxed);
+        PEAK.store(before, Ordering::Relaxed);
+
+        let data = read::read_whole(f, len as u64).await.unwrap();
+
+        let used = PEAK.load(Ordering::Relaxed) - before;
+        assert!(
+            used <= len + ALLOC_SLACK,
+            "reading {len} bytes allocated {used} bytes at most"
+        );
+        data
+    });
+    assert_eq!(data.len(), len);
+}
+
+fn recache(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("recache");
+    group.sample_size(20);
+
+    for &len in SIZES {
+        let path = make_file(len);
+        check_peak_memory(&rt, &path, len);
+
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::new("read_whole", len), &path, |b, path| {
+            b.to_async(&rt).iter(|| async move {
+                let f = File::open(path).await.unwrap();
+                read::read_whole(f, len as u64).await.unwrap()
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("read_buf_loop", len), &path, |b, path| {
+            b.to_async(&rt).iter(|| async move {
+                let f = File::open(path).await.unwrap();
+                read_buf_loop(f, len as u64).await
+            });
+        });
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, recache);
+criterion_main!(benches);
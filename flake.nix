@@ -35,9 +35,11 @@
           ];
       };
 
+      cargoArtifacts = craneLib.buildDepsOnly commonArgs;
+
       breeze = craneLib.buildPackage (commonArgs
         // {
-          cargoArtifacts = craneLib.buildDepsOnly commonArgs;
+          inherit cargoArtifacts;
 
           # Additional environment variables or build phases/hooks can be set
           # here *without* rebuilding all dependency crates
@@ -46,6 +48,14 @@
     in {
       checks = {
         inherit breeze;
+
+        # lint every feature too, so code only built
+        # with optional features (like `direct-io`) can't rot
+        breeze-clippy = craneLib.cargoClippy (commonArgs
+          // {
+            inherit cargoArtifacts;
+            cargoClippyExtraArgs = "--all-targets --all-features -- --deny warnings";
+          });
       };
 
       packages.default = breeze;
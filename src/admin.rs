@@ -0,0 +1,228 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_with::{DurationSeconds, serde_as};
+use tracing::error;
+
+use crate::{
+    engine::{Engine, LoadCacheOutcome},
+    feature::Feature,
+};
+
+#[derive(Deserialize)]
+pub struct AdminRequest {
+    key: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EvictRequest {
+    key: Option<String>,
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct CancelRequest {
+    key: Option<String>,
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct LoadRequest {
+    key: Option<String>,
+    name: String,
+    pin: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DeletionUrlRequest {
+    key: Option<String>,
+    name: String,
+}
+
+#[serde_as]
+#[derive(Deserialize)]
+pub struct PruneRequest {
+    key: Option<String>,
+
+    #[serde_as(as = "Option<DurationSeconds>")]
+    older_than: Option<Duration>,
+
+    #[serde_as(as = "Option<DurationSeconds>")]
+    newer_than: Option<Duration>,
+
+    dry_run: Option<String>,
+}
+
+/// An upload that is being saved right now
+#[derive(Serialize)]
+pub struct InFlightUpload {
+    /// The saved name it's being written to
+    name: String,
+
+    /// How many bytes have been received so far
+    received: u64,
+
+    /// How long it has been going for (in seconds)
+    elapsed: u64,
+}
+
+/// Make sure admin endpoints are enabled and the key given is correct.
+pub fn check_key(engine: &Engine, key: Option<String>) -> Result<(), (StatusCode, &'static str)> {
+    if engine.cfg.admin_key.is_empty() {
+        return Err(Feature::Admin.disabled());
+    }
+
+    if key.unwrap_or_default() != engine.cfg.admin_key {
+        return Err((StatusCode::FORBIDDEN, "Admin key is invalid"));
+    }
+
+    Ok(())
+}
+
+/// The request handler for the /admin/cache/clear path.
+/// This drops everything in the cache.
+pub async fn clear_cache(
+    State(engine): State<Arc<Engine>>,
+    Query(req): Query<AdminRequest>,
+) -> Result<String, (StatusCode, &'static str)> {
+    check_key(&engine, req.key)?;
+
+    let (entries, length) = engine.clear_cache();
+
+    Ok(format!("Cleared {entries} entries ({length} bytes)"))
+}
+
+/// The request handler for the /admin/cache/evict path.
+/// This drops a single upload from the cache.
+pub async fn evict_cache(
+    State(engine): State<Arc<Engine>>,
+    Query(req): Query<EvictRequest>,
+) -> Result<String, (StatusCode, &'static str)> {
+    check_key(&engine, req.key)?;
+
+    match engine.evict_cache(&req.name) {
+        Some(length) => Ok(format!("Cleared 1 entry ({length} bytes)")),
+        None => Err((StatusCode::NOT_FOUND, "Upload is not cached")),
+    }
+}
+
+/// The request handler for the /admin/cache/load path.
+/// This loads a single upload into the cache, even if it's over `cache.max_length`.
+pub async fn load_cache(
+    State(engine): State<Arc<Engine>>,
+    Query(req): Query<LoadRequest>,
+) -> Result<String, (StatusCode, &'static str)> {
+    check_key(&engine, req.key)?;
+
+    let pin = req.pin.as_deref().is_some_and(|p| p == "1" || p == "true");
+
+    match engine.load_cache(&req.name, pin).await {
+        Ok(LoadCacheOutcome::Loaded(length)) => Ok(format!("Loaded 1 entry ({length} bytes)")),
+        Ok(LoadCacheOutcome::NotFound) => Err((StatusCode::NOT_FOUND, "Upload not found")),
+        Ok(LoadCacheOutcome::TooLarge) => Err((
+            StatusCode::INSUFFICIENT_STORAGE,
+            "Upload is too large to fit in the cache",
+        )),
+        Err(err) => {
+            error!("failed to load upload into cache!! {err:#}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Internal server error!"))
+        }
+    }
+}
+
+/// The request handler for the /admin/prune path.
+/// This deletes every upload within an age range (in seconds).
+pub async fn prune(
+    State(engine): State<Arc<Engine>>,
+    Query(req): Query<PruneRequest>,
+) -> Result<String, (StatusCode, &'static str)> {
+    check_key(&engine, req.key)?;
+
+    // don't delete everything just because a filter was forgotten
+    if req.older_than.is_none() && req.newer_than.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "At least one of older_than or newer_than is needed",
+        ));
+    }
+
+    let dry_run = req
+        .dry_run
+        .as_deref()
+        .is_some_and(|d| d == "1" || d == "true");
+
+    match engine
+        .remove_by_age(req.older_than, req.newer_than, dry_run)
+        .await
+    {
+        Ok(count) if dry_run => Ok(format!("Would delete {count} uploads")),
+        Ok(count) => Ok(format!("Deleted {count} uploads")),
+        Err(err) => {
+            error!("failed to prune uploads!! {err:#}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Internal server error!"))
+        }
+    }
+}
+
+/// The request handler for the /admin/deletion-url path.
+/// This works out the deletion URL for an upload again, in case it was lost.
+pub async fn deletion_url(
+    State(engine): State<Arc<Engine>>,
+    Query(req): Query<DeletionUrlRequest>,
+) -> Result<String, (StatusCode, &'static str)> {
+    check_key(&engine, req.key)?;
+
+    if engine.deletion_hmac.is_none() {
+        return Err(Feature::Deletion.disabled());
+    }
+
+    match engine.recover_deletion_url(&req.name).await {
+        Ok(Some(url)) => Ok(url),
+        Ok(None) => Err((StatusCode::NOT_FOUND, "Upload not found")),
+        Err(err) => {
+            error!("failed to recover deletion url!! {err:#}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Internal server error!"))
+        }
+    }
+}
+
+/// The request handler for the /admin/uploads path.
+/// This lists uploads that are being saved right now.
+pub async fn uploads(
+    State(engine): State<Arc<Engine>>,
+    Query(req): Query<AdminRequest>,
+) -> Result<Json<Vec<InFlightUpload>>, (StatusCode, &'static str)> {
+    check_key(&engine, req.key)?;
+
+    let uploads = engine
+        .in_flight_uploads()
+        .into_iter()
+        .map(|(name, received, elapsed)| InFlightUpload {
+            name,
+            received,
+            elapsed: elapsed.as_secs(),
+        })
+        .collect();
+
+    Ok(Json(uploads))
+}
+
+/// The request handler for the /admin/uploads/cancel path.
+/// This stops an upload that is being saved, and removes what it saved.
+pub async fn cancel_upload(
+    State(engine): State<Arc<Engine>>,
+    Query(req): Query<CancelRequest>,
+) -> Result<&'static str, (StatusCode, &'static str)> {
+    check_key(&engine, req.key)?;
+
+    if engine.cancel_upload(&req.name) {
+        Ok("Cancelled upload")
+    } else {
+        Err((StatusCode::NOT_FOUND, "Upload is not being saved"))
+    }
+}
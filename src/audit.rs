@@ -0,0 +1,163 @@
+use std::{net::IpAddr, path::Path, time::SystemTime};
+
+use serde::Serialize;
+use tokio::{
+    fs::File,
+    io::AsyncWriteExt,
+    sync::{mpsc, oneshot},
+};
+use tracing::error;
+
+use crate::view::fmt_rfc3339;
+
+/// What happened to an upload
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// It was uploaded
+    Upload,
+
+    /// It was deleted by a client, with a deletion URL
+    /// or by giving it an expiry that already passed
+    Delete,
+
+    /// It was pruned by an admin
+    Prune,
+}
+
+/// A single line in the audit log
+#[derive(Serialize)]
+struct AuditEvent {
+    /// When it happened (RFC 3339)
+    time: String,
+
+    /// What happened
+    action: AuditAction,
+
+    /// The saved name of the upload it happened to
+    name: String,
+
+    /// Length of the upload, if it's known
+    size: Option<u64>,
+
+    /// IP address of the client responsible, if there was one
+    ip: Option<IpAddr>,
+}
+
+/// What the writer task is sent
+enum Message {
+    /// A line to write
+    Event(AuditEvent),
+
+    /// Reply once everything sent before this is written
+    Flush(oneshot::Sender<()>),
+}
+
+/// An append-only log of uploads and deletions, written as JSON lines.
+///
+/// Lines are written by a dedicated task, so recording
+/// an event never waits on the disk.
+pub struct AuditLog {
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+impl AuditLog {
+    /// Open (or create) the log file and start the task that writes to it
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let mut file = File::from_std(file);
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                let event = match msg {
+                    Message::Event(event) => event,
+                    Message::Flush(done) => {
+                        let _ = done.send(());
+                        continue;
+                    }
+                };
+
+                let mut line = serde_json::to_vec(&event).expect("serialize audit event failed");
+                line.push(b'\n');
+
+                // the upload already happened either way, so just make noise
+                let res = async {
+                    file.write_all(&line).await?;
+                    file.flush().await
+                };
+                if let Err(err) = res.await {
+                    error!(%err, "failed to write to audit log!");
+                }
+            }
+        });
+
+        Ok(Self { tx })
+    }
+
+    /// Record that something happened to an upload
+    pub fn record(
+        &self,
+        action: AuditAction,
+        saved_name: &str,
+        size: Option<u64>,
+        ip: Option<IpAddr>,
+    ) {
+        let event = AuditEvent {
+            time: fmt_rfc3339(SystemTime::now()),
+            action,
+            name: saved_name.to_string(),
+            size,
+            ip,
+        };
+
+        if self.tx.send(Message::Event(event)).is_err() {
+            error!("audit log task is gone, event was not recorded!");
+        }
+    }
+
+    /// Wait for every event recorded so far to be written
+    pub async fn flush(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.tx.send(Message::Flush(done_tx)).is_ok() {
+            let _ = done_rx.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditAction, AuditLog};
+
+    /// Make sure events are appended as JSON lines
+    #[tokio::test]
+    async fn appends_lines() {
+        let path = std::env::temp_dir().join(format!("breeze-audit-test-{}", std::process::id()));
+
+        let log = AuditLog::open(&path).unwrap();
+        log.record(AuditAction::Upload, "abcdef.png", Some(8), None);
+        log.record(
+            AuditAction::Delete,
+            "abcdef.png",
+            None,
+            Some("127.0.0.1".parse().unwrap()),
+        );
+
+        log.flush().await;
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines: Vec<serde_json::Value> = written
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["action"], "upload");
+        assert_eq!(lines[0]["size"], 8);
+        assert_eq!(lines[1]["action"], "delete");
+        assert_eq!(lines[1]["ip"], "127.0.0.1");
+    }
+}
@@ -1,5 +1,9 @@
 use std::{
-    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    collections::{HashMap, VecDeque},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
     time::Duration,
 };
 
@@ -32,6 +36,15 @@ pub struct Entry {
 
     /// How long the entry should last
     lifetime: Duration,
+
+    /// Modification time of the file on disk this entry was cached from, if known
+    mtime: Option<std::time::SystemTime>,
+
+    /// When the upload this entry holds was created
+    created: std::time::SystemTime,
+
+    /// Whether the entry should stay cached no matter what
+    pinned: bool,
 }
 
 impl Entry {
@@ -43,6 +56,9 @@ impl Entry {
             last_used: now,
             update_used,
             lifetime,
+            mtime: None,
+            created: std::time::SystemTime::now(),
+            pinned: false,
         }
     }
 
@@ -51,6 +67,10 @@ impl Entry {
     }
 
     fn is_expired(&self) -> bool {
+        if self.pinned {
+            return false;
+        }
+
         match self.last_used().elapsed() {
             Ok(d) => d >= self.lifetime,
             Err(_) => false, // now > last_used
@@ -58,6 +78,30 @@ impl Entry {
     }
 }
 
+/// Names of temporary uploads that expired recently, and when they did
+#[derive(Default)]
+struct Tombstones {
+    /// Oldest first, so the front is what's forgotten next
+    order: VecDeque<(String, SystemTime)>,
+
+    /// For looking names up. A name buried twice keeps its latest time
+    at: HashMap<String, SystemTime>,
+}
+
+impl Tombstones {
+    /// Forget the oldest tombstone
+    fn pop(&mut self) {
+        let Some((key, buried)) = self.order.pop_front() else {
+            return;
+        };
+
+        // it could have been buried again since
+        if self.at.get(&key) == Some(&buried) {
+            self.at.remove(&key);
+        }
+    }
+}
+
 /// A concurrent cache with a maximum memory size (w/ LRU) and expiration.
 ///
 /// It is designed to keep memory usage low.
@@ -68,10 +112,20 @@ pub struct Cache {
     /// Total length of data stored in cache currently
     length: AtomicUsize,
 
+    /// How many temporary (non-renewable) entries are stored currently
+    temp_count: AtomicUsize,
+
     /// How many times the scanner has ran,
     /// for testing purposes
     scan_count: AtomicU64,
 
+    /// Temporary uploads that expired recently
+    tombstones: Mutex<Tombstones>,
+
+    /// How many times uploads too large to cache normally
+    /// have been viewed from disk since the last scan
+    disk_views: DashMap<String, u32>,
+
     /// How should it behave
     cfg: config::CacheConfig,
 }
@@ -82,20 +136,35 @@ impl Cache {
         if cfg.mem_capacity < cfg.max_length {
             bail!("`max_length` should not exceed `mem_capacity`");
         }
+        if cfg
+            .large_promote_max
+            .is_some_and(|max| max > cfg.mem_capacity)
+        {
+            bail!("`large_promote_max` should not exceed `mem_capacity`");
+        }
 
         // Return
         Ok(Self {
             map: DashMap::with_capacity(64),
             length: AtomicUsize::new(0),
+            temp_count: AtomicUsize::new(0),
             scan_count: AtomicU64::new(0),
+            tombstones: Mutex::default(),
+            disk_views: DashMap::new(),
 
             cfg,
         })
     }
 
-    /// Figure out who should be bumped out of cache next
-    fn next_out(&self, length: usize) -> Vec<String> {
-        let mut sorted: Vec<_> = self.map.iter().collect();
+    /// Figure out who should be bumped out of cache next,
+    /// only picking entries that have expired if `only_expired` is set
+    fn next_out(&self, length: usize, only_expired: bool) -> Vec<String> {
+        // Pinned entries are never bumped out
+        let mut sorted: Vec<_> = self
+            .map
+            .iter()
+            .filter(|e| !e.pinned && (!only_expired || e.is_expired()))
+            .collect();
 
         // Sort by least recently used
         sorted.sort_unstable_by_key(|e| e.last_used());
@@ -121,37 +190,129 @@ impl Cache {
 
     /// Remove an element from the cache
     ///
-    /// Returns: [`Some`] if successful, [`None`] if element not found
-    pub fn remove(&self, key: &str) -> Option<()> {
+    /// Returns: [`Some`] with the length of the removed value if successful,
+    /// [`None`] if element not found
+    pub fn remove(&self, key: &str) -> Option<usize> {
         // Skip expiry checks, we are removing it anyways
         // And also that could cause an infinite loop which would be pretty stupid.
-        let e = self.map.get(key)?;
+        let (_, e) = self.map.remove(key)?;
 
+        self.forget(&e);
+
+        Some(e.value.len())
+    }
+
+    /// Move an entry to a different key, replacing anything that was there.
+    /// If there's no entry to move, the old one under `to` is still removed.
+    ///
+    /// Returns: `true` if an entry was moved
+    pub fn rename(&self, from: &str, to: &str) -> bool {
+        self.remove(to);
+
+        let Some((_, e)) = self.map.remove(from) else {
+            return false;
+        };
+
+        // the totals don't change, it's the same entry
+        if let Some(old) = self.map.insert(to.to_string(), e) {
+            self.forget(&old);
+        }
+
+        true
+    }
+
+    /// Update our totals for an entry that was just taken out of the map
+    fn forget(&self, e: &Entry) {
         // Atomically subtract from the total cache length
         self.length.fetch_sub(e.value.len(), Ordering::Relaxed);
 
-        // Drop the entry lock so we can actually remove it
-        drop(e);
+        if !e.update_used {
+            self.temp_count.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Remember that a temporary upload expired, dropping the oldest
+    /// tombstones if there are too many
+    fn bury(&self, key: &str) {
+        if self.cfg.tombstones == 0 {
+            return;
+        }
+
+        let now = SystemTime::now();
+        let mut tombstones = self.tombstones.lock().unwrap();
 
-        // Remove from map
-        self.map.remove(key);
+        tombstones.order.push_back((key.to_string(), now));
+        tombstones.at.insert(key.to_string(), now);
+        while tombstones.order.len() > self.cfg.tombstones {
+            tombstones.pop();
+        }
+    }
 
-        Some(())
+    /// Check if a temporary upload expired recently (within `tombstone_lifetime`)
+    pub fn expired_recently(&self, key: &str) -> bool {
+        let tombstones = self.tombstones.lock().unwrap();
+
+        tombstones.at.get(key).is_some_and(|buried| {
+            SystemTime::now()
+                .duration_since(*buried)
+                .is_ok_and(|d| d < self.cfg.tombstone_lifetime)
+        })
+    }
+
+    /// Forget tombstones that are older than `tombstone_lifetime`
+    fn prune_tombstones(&self, now: SystemTime) {
+        let mut tombstones = self.tombstones.lock().unwrap();
+
+        while tombstones.order.front().is_some_and(|(_, buried)| {
+            now.duration_since(*buried)
+                .is_ok_and(|d| d >= self.cfg.tombstone_lifetime)
+        }) {
+            tombstones.pop();
+        }
+    }
+
+    /// Remove every element from the cache
+    ///
+    /// Returns: how many entries were removed, and their total length
+    pub fn clear(&self) -> (usize, usize) {
+        let mut entries = 0;
+        let mut length = 0;
+
+        // Use a retain call so we can count what we drop
+        self.map.retain(|_, e| {
+            entries += 1;
+            length += e.value.len();
+            self.forget(e);
+
+            false
+        });
+
+        (entries, length)
     }
 
     /// Add a new element to the cache with a specified lifetime.
     ///
-    /// Returns: `true` if no value is replaced, `false` if a value was replaced
+    /// Returns: `Some(true)` if no value is replaced, `Some(false)` if a value was replaced,
+    /// [`None`] if there isn't enough space for it (nothing is cached under the key then)
     pub fn add_with_lifetime(
         &self,
         key: &str,
         value: Bytes,
         lifetime: Duration,
         is_renewable: bool,
-    ) -> bool {
+    ) -> Option<bool> {
         let e = Entry::new(value, lifetime, is_renewable);
-
         let len = e.value.len();
+
+        // Take out the value being replaced first,
+        // so it isn't counted against the space we have
+        let replaced = self.remove(key).is_some();
+
+        // Don't bump everything else out for something that will never fit
+        if len > self.cfg.mem_capacity {
+            return None;
+        }
+
         let cur_total = self.length.load(Ordering::Relaxed);
         let new_total = cur_total + len;
 
@@ -159,26 +320,54 @@ impl Cache {
             // How far we went above the limit
             let needed = new_total - self.cfg.mem_capacity;
 
-            self.next_out(needed).iter().for_each(|k| {
+            // Temporary uploads might not be allowed to bump out live entries
+            let only_expired = !is_renewable && self.cfg.temp_never_evicts;
+
+            self.next_out(needed, only_expired).iter().for_each(|k| {
                 // Remove the element, and ignore the result
                 // The only reason it should be failing is if it couldn't find it,
                 // in which case it was already removed
                 self.remove(k);
             });
+
+            // Pinned entries (or live ones) can't be bumped out,
+            // so there might still not be enough space
+            if self.length.load(Ordering::Relaxed) + len > self.cfg.mem_capacity {
+                return None;
+            }
         }
 
         // Atomically add to total cached data length
         self.length.fetch_add(len, Ordering::Relaxed);
 
-        // Add to the map, return true if we didn't replace anything
-        self.map.insert(key.to_string(), e).is_none()
+        if !is_renewable {
+            self.temp_count.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // Add to the map. Something else could have been added in the meantime
+        match self.map.insert(key.to_string(), e) {
+            Some(old) => {
+                self.forget(&old);
+                Some(false)
+            }
+            None => Some(!replaced),
+        }
+    }
+
+    /// Add a permanent upload to the cache, staying for `default_residency`
+    /// after it was last used.
+    ///
+    /// Returns: same as [`Cache::add_with_lifetime`]
+    pub fn add(&self, key: &str, value: Bytes) -> Option<bool> {
+        self.add_with_lifetime(key, value, self.cfg.default_residency, true)
     }
 
-    /// Add a new element to the cache with the default lifetime.
+    /// Add a temporary upload to the cache. It expires once its lifetime is up,
+    /// no matter how much it's used.
     ///
-    /// Returns: `true` if no value is replaced, `false` if a value was replaced
-    pub fn add(&self, key: &str, value: Bytes) -> bool {
-        self.add_with_lifetime(key, value, self.cfg.upload_lifetime, true)
+    /// Returns: same as [`Cache::add_with_lifetime`]
+    pub fn add_temporary(&self, key: &str, value: Bytes, lifetime: Duration) -> Option<bool> {
+        self.add_with_lifetime(key, value, lifetime, false)
     }
 
     /// Internal function for retrieving entries.
@@ -192,11 +381,16 @@ impl Cache {
 
         // if the entry is expired get rid of it now
         if e.is_expired() {
+            let is_temp = !e.update_used;
+
             // drop the reference so we don't deadlock
             drop(e);
 
             // remove it
             self.remove(key);
+            if is_temp {
+                self.bury(key);
+            }
 
             // and say we never had it
             return None;
@@ -216,6 +410,108 @@ impl Cache {
         Some(e.value.clone())
     }
 
+    /// Get the length of an entry's data, if it exists.
+    /// Unlike [`Cache::get`], this doesn't count as using it.
+    pub fn length_of(&self, key: &str) -> Option<usize> {
+        self.get_(key).map(|e| e.value.len())
+    }
+
+    /// Get when a temporary upload held by an entry will expire.
+    /// Other entries only expire from the cache, not for good, so they get [`None`].
+    pub fn expires_at(&self, key: &str) -> Option<std::time::SystemTime> {
+        self.get_(key).filter(|e| !e.update_used).map(|e| {
+            // these aren't kept alive by reads, so `last_used` is when the
+            // lifetime started. that's only `created` if it was never changed
+            let age = e.last_used().elapsed().unwrap_or_default();
+            std::time::SystemTime::now() + e.lifetime.saturating_sub(age)
+        })
+    }
+
+    /// Get when the upload held by an entry was created, if it exists.
+    pub fn created(&self, key: &str) -> Option<std::time::SystemTime> {
+        self.get_(key).map(|e| e.created)
+    }
+
+    /// Set when the upload held by an entry was created.
+    ///
+    /// This is for entries cached from existing files,
+    /// which were created before they were cached.
+    pub fn set_created(&self, key: &str, created: std::time::SystemTime) {
+        if let Some(mut e) = self.map.get_mut(key) {
+            e.created = created;
+        }
+    }
+
+    /// Change how long an entry lasts, in place.
+    ///
+    /// With a lifetime, it becomes (or stays) temporary and expires that long from now,
+    /// no matter how much it's used. An entry that wasn't temporary keeps
+    /// when it was created. Without a lifetime, it becomes permanent and stays
+    /// for `default_residency` after it was last used.
+    ///
+    /// Returns: `true` if the entry exists
+    pub fn set_lifetime(&self, key: &str, lifetime: Option<Duration>) -> bool {
+        let Some(mut e) = self.map.get_mut(key) else {
+            return false;
+        };
+
+        match lifetime {
+            // temporary entries are timed from when they were created
+            Some(lifetime) if !e.update_used => {
+                let age = e.last_used().elapsed().unwrap_or_default();
+                e.lifetime = age + lifetime;
+            }
+            Some(lifetime) => {
+                e.last_used.store(SystemTime::now(), Ordering::Relaxed);
+                e.update_used = false;
+                e.lifetime = lifetime;
+                self.temp_count.fetch_add(1, Ordering::Relaxed);
+            }
+            None => {
+                if !e.update_used {
+                    self.temp_count.fetch_sub(1, Ordering::Relaxed);
+                }
+                e.last_used.store(SystemTime::now(), Ordering::Relaxed);
+                e.update_used = true;
+                e.lifetime = self.cfg.default_residency;
+            }
+        }
+
+        true
+    }
+
+    /// Pin an entry, so it never expires or gets bumped out.
+    /// It can still be removed directly.
+    ///
+    /// Returns: `true` if the entry exists
+    pub fn pin(&self, key: &str) -> bool {
+        let Some(mut e) = self.map.get_mut(key) else {
+            return false;
+        };
+
+        e.pinned = true;
+        true
+    }
+
+    /// Compare an entry's recorded disk modification time with the given one.
+    /// If the entry doesn't have one recorded yet, the given one is recorded.
+    ///
+    /// Returns: `false` if the entry exists and was cached from a different version
+    /// of the file, `true` otherwise
+    pub fn sync_mtime(&self, key: &str, mtime: std::time::SystemTime) -> bool {
+        let Some(mut e) = self.map.get_mut(key) else {
+            return true;
+        };
+
+        match e.mtime {
+            Some(recorded) => recorded == mtime,
+            None => {
+                e.mtime = Some(mtime);
+                true
+            }
+        }
+    }
+
     /// Check if we have an item in cache.
     ///
     /// Returns: `true` if key exists, `false` if it doesn't
@@ -226,11 +522,92 @@ impl Cache {
         self.get_(key).is_some()
     }
 
+    /// How many entries are stored in the cache
+    pub fn entry_count(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Total length of data stored in the cache
+    pub fn total_length(&self) -> usize {
+        self.length.load(Ordering::Relaxed)
+    }
+
+    /// How many temporary uploads are stored in the cache
+    pub fn temp_count(&self) -> usize {
+        self.temp_count.load(Ordering::Relaxed)
+    }
+
+    /// The maximum length an upload can be to be cached
+    pub fn max_length(&self) -> usize {
+        self.cfg.max_length
+    }
+
+    /// Returns if an upload could fit in the cache at all,
+    /// ignoring `max_length`
+    pub fn could_fit(&self, length: u64) -> bool {
+        length <= (self.cfg.mem_capacity as u64)
+    }
+
     /// Returns if an upload is able to be cached
     /// with the current caching rules
     #[inline]
-    pub fn will_use(&self, length: u64) -> bool {
-        length <= (self.cfg.max_length as u64)
+    pub fn will_use(&self, ext: Option<&str>, length: u64) -> bool {
+        if length > (self.cfg.max_length as u64) || !self.is_cacheable_type(ext) {
+            return false;
+        }
+
+        self.has_room(length)
+    }
+
+    /// Count a view of an upload too large to be cached normally, and
+    /// returns if it's now been viewed enough to be cached anyway.
+    ///
+    /// Views are counted from the last scan, so only uploads
+    /// that are popular right now are cached.
+    pub fn should_promote(&self, key: &str, ext: Option<&str>, length: u64) -> bool {
+        let Some(promote_max) = self.cfg.large_promote_max else {
+            return false;
+        };
+        if length > (promote_max as u64) || !self.is_cacheable_type(ext) {
+            return false;
+        }
+
+        let mut views = self.disk_views.entry(key.to_string()).or_default();
+        *views += 1;
+        if *views < self.cfg.large_promote_after {
+            return false;
+        }
+        drop(views);
+
+        // start counting over, so only this view caches it
+        self.disk_views.remove(key);
+        self.has_room(length)
+    }
+
+    /// Returns if there is room for an upload of a length,
+    /// going by whether we're allowed to evict anything to make some
+    fn has_room(&self, length: u64) -> bool {
+        // if we're avoiding thrash, don't evict anything to make room
+        if self.cfg.avoid_thrash {
+            let free = self
+                .cfg
+                .mem_capacity
+                .saturating_sub(self.length.load(Ordering::Relaxed));
+            return length <= (free as u64);
+        }
+
+        true
+    }
+
+    /// Returns if uploads with an extension are allowed to be cached,
+    /// going by `cacheable_types` and `excluded_types`
+    fn is_cacheable_type(&self, ext: Option<&str>) -> bool {
+        // only the last part of combined extensions (like `tar.gz`) matters
+        let ext = ext.and_then(|e| e.rsplit('.').next()).unwrap_or_default();
+        let listed = |types: &[String]| types.iter().any(|t| t.eq_ignore_ascii_case(ext));
+
+        (self.cfg.cacheable_types.is_empty() || listed(&self.cfg.cacheable_types))
+            && !listed(&self.cfg.excluded_types)
     }
 
     /// The background job that scans through the cache and removes inactive elements.
@@ -246,6 +623,7 @@ impl Cache {
             // We put this first so that it doesn't scan the instant the server starts
             interval.tick().await;
             self.scan_count.fetch_add(1, Ordering::Relaxed);
+            let scan_start = std::time::Instant::now();
 
             // Save current timestamp so we aren't retrieving it constantly
             // If we don't do this it'll be a LOT of system api calls
@@ -258,7 +636,7 @@ impl Cache {
                 .iter()
                 .filter_map(|e| {
                     let elapsed = now.duration_since(e.last_used()).unwrap_or(Duration::MAX);
-                    let is_expired = elapsed >= e.lifetime;
+                    let is_expired = !e.pinned && elapsed >= e.lifetime;
 
                     if is_expired {
                         Some(e.key().clone())
@@ -269,10 +647,46 @@ impl Cache {
                 .collect();
 
             // If we have any, lock the map and drop all of them
+            let (mut reclaimed, mut reclaimed_len) = (0, 0);
+            let mut buried = Vec::new();
             if !expired.is_empty() {
                 // Use a retain call, should be less locks that way
                 // (instead of many remove calls)
-                self.map.retain(|k, _| !expired.contains(k));
+                self.map.retain(|k, e| {
+                    let keep = !expired.contains(k);
+
+                    if !keep {
+                        reclaimed += 1;
+                        reclaimed_len += e.value.len();
+                        self.forget(e);
+
+                        if !e.update_used {
+                            buried.push(k.clone());
+                        }
+                    }
+
+                    keep
+                });
+            }
+            for key in &buried {
+                self.bury(key);
+            }
+            self.prune_tombstones(now);
+
+            // views of large uploads only count until the next scan
+            self.disk_views.clear();
+
+            // Log what was done, so scan_freq and default_residency can be tuned
+            let took = scan_start.elapsed();
+            if reclaimed > 0 {
+                tracing::info!(
+                    reclaimed,
+                    reclaimed_len,
+                    ?took,
+                    "cache scan reclaimed entries"
+                );
+            } else {
+                tracing::debug!(?took, "cache scan found nothing to reclaim");
             }
         }
     }
@@ -293,7 +707,7 @@ mod tests {
     use crate::{cache::Cache, config::CacheConfig};
 
     thread_local! {
-        static MOCK_CLOCK: AtomicU64 = AtomicU64::new(0);
+        static MOCK_CLOCK: AtomicU64 = const { AtomicU64::new(0) };
     }
     fn get_clock() -> u64 {
         MOCK_CLOCK.with(|mc| mc.load(Ordering::Relaxed))
@@ -349,14 +763,27 @@ mod tests {
     const KEY: &str = "abcdef.png";
     const VALUE: Bytes = Bytes::from_static(&[0, 1, 2, 3, 4, 5, 6, 7]);
 
-    fn simple() -> Cache {
-        return Cache::with_config(CacheConfig {
+    /// Config for a roomy cache with nothing special turned on.
+    /// Tests only change the fields they're about
+    fn base() -> CacheConfig {
+        CacheConfig {
             max_length: 10_000_000,
             mem_capacity: 100_000_000,
             scan_freq: Duration::from_secs(5),
-            upload_lifetime: Duration::from_secs(15),
-        })
-        .unwrap();
+            default_residency: Duration::from_secs(15),
+            avoid_thrash: false,
+            temp_never_evicts: false,
+            cacheable_types: Vec::new(),
+            excluded_types: Vec::new(),
+            tombstones: 2,
+            tombstone_lifetime: Duration::from_secs(60),
+            large_promote_max: None,
+            large_promote_after: 10,
+        }
+    }
+
+    fn simple() -> Cache {
+        Cache::with_config(base()).unwrap()
     }
 
     async fn scanning() -> Arc<Cache> {
@@ -379,16 +806,92 @@ mod tests {
         let cache = simple();
 
         // use something
-        assert!(cache.will_use(4_000_000));
+        assert!(cache.will_use(None, 4_000_000));
 
         // don't use something
-        assert!(!cache.will_use(12_000_001));
+        assert!(!cache.will_use(None, 12_000_001));
 
         // use something edge
-        assert!(cache.will_use(10_000_000));
+        assert!(cache.will_use(None, 10_000_000));
 
         // use something mini
-        assert!(cache.will_use(0));
+        assert!(cache.will_use(None, 0));
+    }
+
+    /// Make sure uploads too large to cache normally
+    /// are only cached once they've been viewed enough
+    #[test]
+    fn promote_large() {
+        let cache = Cache::with_config(CacheConfig {
+            max_length: 10,
+            mem_capacity: 1000,
+            excluded_types: vec!["mp4".to_string()],
+            large_promote_max: Some(100),
+            large_promote_after: 3,
+            ..base()
+        })
+        .unwrap();
+
+        // the third view caches it, then counting starts over
+        assert!(!cache.should_promote(KEY, Some("png"), 50));
+        assert!(!cache.should_promote(KEY, Some("png"), 50));
+        assert!(cache.should_promote(KEY, Some("png"), 50));
+        assert!(!cache.should_promote(KEY, Some("png"), 50));
+
+        // too large, or not allowed to be cached at all
+        for _ in 0..3 {
+            assert!(!cache.should_promote("big.png", Some("png"), 101));
+            assert!(!cache.should_promote("video.mp4", Some("mp4"), 50));
+        }
+    }
+
+    /// Make sure that only the types of uploads
+    /// that are allowed to be cached are
+    #[test]
+    fn cacheable_types() {
+        let cache = Cache::with_config(CacheConfig {
+            max_length: 16,
+            mem_capacity: 16,
+            cacheable_types: vec!["png".into(), "gz".into()],
+            excluded_types: vec!["gz".into()],
+            ..base()
+        })
+        .unwrap();
+
+        assert!(cache.will_use(Some("png"), 8));
+        assert!(cache.will_use(Some("PNG"), 8));
+        assert!(!cache.will_use(Some("png"), 17));
+        assert!(!cache.will_use(Some("mp4"), 8));
+        assert!(!cache.will_use(None, 8));
+
+        // excluding wins, and combined extensions go by the last part
+        assert!(!cache.will_use(Some("tar.gz"), 8));
+    }
+
+    /// Make sure that uploads aren't cached if they'd
+    /// bump something else out with `avoid_thrash` on
+    #[test]
+    fn avoid_thrash() {
+        let cache = Cache::with_config(CacheConfig {
+            max_length: 12,
+            mem_capacity: 16,
+            avoid_thrash: true,
+            ..base()
+        })
+        .unwrap();
+
+        // there's room for anything under max_length
+        assert!(cache.will_use(None, 12));
+        assert!(!cache.will_use(None, 13));
+
+        // only what fits in the free space now
+        assert_eq!(cache.add(KEY, VALUE), Some(true));
+        assert!(cache.will_use(None, 8));
+        assert!(!cache.will_use(None, 9));
+
+        // room again once it's gone
+        cache.remove(KEY);
+        assert!(cache.will_use(None, 12));
     }
 
     /// Make sure that [`Cache::add`]'s return value
@@ -398,10 +901,257 @@ mod tests {
         let cache = simple();
 
         // store
-        assert!(cache.add(KEY, VALUE));
+        assert_eq!(cache.add(KEY, VALUE), Some(true));
 
         // store w replace
-        assert!(!cache.add(KEY, VALUE));
+        assert_eq!(cache.add(KEY, VALUE), Some(false));
+    }
+
+    /// Make sure that values that can't fit are skipped
+    /// without bumping everything else out
+    #[test]
+    fn store_too_large() {
+        let cache = Cache::with_config(CacheConfig {
+            max_length: 16,
+            mem_capacity: 16,
+            ..base()
+        })
+        .unwrap();
+
+        // fill it up
+        assert_eq!(cache.add(KEY, VALUE), Some(true));
+        assert_eq!(cache.add("ghijkl.png", VALUE), Some(true));
+
+        // bigger than the whole cache, nothing changes
+        let huge = Bytes::from_static(&[0; 17]);
+        assert_eq!(cache.add("mnopqr.png", huge), None);
+        assert!(cache.has(KEY));
+        assert_eq!(cache.total_length(), 16);
+
+        // fits once the others are bumped out
+        let big = Bytes::from_static(&[0; 12]);
+        assert_eq!(cache.add("mnopqr.png", big), Some(true));
+        assert_eq!(cache.entry_count(), 1);
+        assert_eq!(cache.total_length(), 12);
+
+        // can't bump out a pinned entry to make space
+        assert!(cache.pin("mnopqr.png"));
+        assert_eq!(cache.add(KEY, VALUE), None);
+        assert!(!cache.has(KEY));
+        assert_eq!(cache.total_length(), 12);
+    }
+
+    /// Make sure temporary uploads don't bump out live entries
+    /// with `temp_never_evicts` on, but still bump out expired ones
+    #[test]
+    fn temp_never_evicts() {
+        let cache = Cache::with_config(CacheConfig {
+            max_length: 16,
+            mem_capacity: 16,
+            temp_never_evicts: true,
+            ..base()
+        })
+        .unwrap();
+
+        // fill it up
+        let lifetime = Duration::from_secs(3);
+        assert_eq!(cache.add_temporary(KEY, VALUE, lifetime), Some(true));
+        assert_eq!(cache.add("ghijkl.png", VALUE), Some(true));
+
+        // no room without bumping something live out
+        assert_eq!(cache.add_temporary("mnopqr.png", VALUE, lifetime), None);
+        assert!(cache.has(KEY));
+        assert!(cache.has("ghijkl.png"));
+        assert_eq!(cache.total_length(), 16);
+
+        // once the first has expired, its space can be taken
+        advance_clock(3000);
+        assert_eq!(
+            cache.add_temporary("mnopqr.png", VALUE, lifetime),
+            Some(true)
+        );
+        assert!(!cache.has(KEY));
+        assert!(cache.has("ghijkl.png"));
+
+        // permanent uploads still bump others out
+        assert_eq!(cache.add("stuvwx.png", VALUE), Some(true));
+        assert_eq!(cache.entry_count(), 2);
+    }
+
+    /// Make sure that [`Cache::rename`] replaces what was
+    /// under the new key, and keeps the totals right
+    #[test]
+    fn rename() {
+        let cache = simple();
+
+        assert_eq!(cache.add(KEY, VALUE), Some(true));
+        assert_eq!(cache.add(".part", Bytes::from_static(b"new")), Some(true));
+
+        assert!(cache.rename(".part", KEY));
+        assert_eq!(cache.get(KEY).unwrap(), Bytes::from_static(b"new"));
+        assert!(!cache.has(".part"));
+        assert_eq!(cache.entry_count(), 1);
+        assert_eq!(cache.total_length(), 3);
+
+        // nothing to move, but the old entry still goes
+        assert!(!cache.rename(".part", KEY));
+        assert!(!cache.has(KEY));
+        assert_eq!(cache.total_length(), 0);
+    }
+
+    /// Make sure that [`Cache::clear`] removes
+    /// everything and reports it properly
+    #[test]
+    fn clear() {
+        let cache = simple();
+
+        // store a couple things
+        assert_eq!(cache.add(KEY, VALUE), Some(true));
+        assert_eq!(cache.add("ghijkl.png", VALUE), Some(true));
+
+        // clear them out
+        assert_eq!(cache.clear(), (2, VALUE.len() * 2));
+        assert_eq!(cache.length.load(Ordering::Relaxed), 0);
+        assert!(cache.get(KEY).is_none());
+
+        // nothing left to clear
+        assert_eq!(cache.clear(), (0, 0));
+    }
+
+    /// Make sure that pinned entries don't expire
+    /// or get bumped out to make space
+    #[tokio::test(start_paused = true)]
+    async fn pinned() {
+        let cache = scanning().await;
+
+        // pin one of two entries
+        assert_eq!(cache.add(KEY, VALUE), Some(true));
+        assert_eq!(cache.add("ghijkl.png", VALUE), Some(true));
+        assert!(cache.pin(KEY));
+        assert!(!cache.pin("mnopqr.png"));
+
+        // only the unpinned one would be bumped out
+        assert_eq!(cache.next_out(VALUE.len() * 2, false), vec!["ghijkl.png"]);
+
+        // only the unpinned one expires
+        advance_clock_async(20000).await;
+        assert!(cache.has(KEY));
+        assert!(!cache.has("ghijkl.png"));
+    }
+
+    /// Make sure that temporary entries are counted,
+    /// and stop being counted once they expire
+    #[tokio::test(start_paused = true)]
+    async fn temp_count() {
+        let cache = scanning().await;
+
+        // store one permanent and one temporary
+        assert_eq!(cache.add(KEY, VALUE), Some(true));
+        assert_eq!(
+            cache.add_with_lifetime("ghijkl.png", VALUE, Duration::from_secs(3), false),
+            Some(true)
+        );
+        assert_eq!(cache.temp_count(), 1);
+        assert_eq!(cache.length.load(Ordering::Relaxed), VALUE.len() * 2);
+
+        // temporary entry gets hit by scanner
+        advance_clock_async(5000).await;
+        assert_eq!(cache.temp_count(), 0);
+        assert_eq!(cache.length.load(Ordering::Relaxed), VALUE.len());
+    }
+
+    /// Make sure entries can be made temporary and permanent in place,
+    /// and are counted and expired that way
+    #[test]
+    fn set_lifetime() {
+        let cache = simple();
+        assert!(!cache.set_lifetime(KEY, None));
+
+        // permanent to temporary, which is timed from now
+        cache.add(KEY, VALUE);
+        advance_clock(10_000);
+        assert!(cache.set_lifetime(KEY, Some(Duration::from_secs(3))));
+        assert_eq!(cache.temp_count(), 1);
+        assert!(cache.expires_at(KEY).is_some());
+
+        // a new lifetime for a temporary entry counts from now too
+        advance_clock(2000);
+        assert!(cache.set_lifetime(KEY, Some(Duration::from_secs(3))));
+        advance_clock(2000);
+        assert!(cache.get(KEY).is_some());
+
+        // back to permanent, so it's renewed by use again
+        assert!(cache.set_lifetime(KEY, None));
+        assert_eq!(cache.temp_count(), 0);
+        assert!(cache.expires_at(KEY).is_none());
+        advance_clock(10_000);
+        assert!(cache.get(KEY).is_some());
+        advance_clock(10_000);
+        assert!(cache.get(KEY).is_some());
+
+        // and expired once it's been left alone long enough
+        advance_clock(15_000);
+        assert!(cache.get(KEY).is_none());
+    }
+
+    /// Make sure expired temporary uploads are remembered for a while,
+    /// but not permanent ones, and not too many
+    #[tokio::test(start_paused = true)]
+    async fn tombstones() {
+        let cache = scanning().await;
+
+        cache.add(KEY, VALUE);
+        for key in ["a.png", "b.png", "c.png"] {
+            cache.add_temporary(key, VALUE, Duration::from_secs(3));
+        }
+
+        // expired ones are caught by the scanner
+        advance_clock_async(5000).await;
+        assert!(!cache.has("a.png"));
+
+        // only 2 are kept
+        let remembered = ["a.png", "b.png", "c.png"]
+            .iter()
+            .filter(|k| cache.expired_recently(k))
+            .count();
+        assert_eq!(remembered, 2);
+
+        // or when they're read
+        cache.add_temporary("d.png", VALUE, Duration::from_secs(1));
+        advance_clock(2000);
+        assert!(cache.get("d.png").is_none());
+        assert!(cache.expired_recently("d.png"));
+
+        // permanent uploads only leave the cache, so they aren't gone
+        advance_clock_async(20000).await;
+        assert!(!cache.has(KEY));
+        assert!(!cache.expired_recently(KEY));
+
+        // and everything is forgotten eventually
+        advance_clock_async(60000).await;
+        assert!(!cache.expired_recently("d.png"));
+    }
+
+    /// Make sure that [`Cache::sync_mtime`] records
+    /// the first time it is given and catches changes
+    #[test]
+    fn sync_mtime() {
+        let cache = simple();
+        let first = std::time::SystemTime::UNIX_EPOCH;
+        let second = first + Duration::from_secs(1);
+
+        // nothing to compare against
+        assert!(cache.sync_mtime(KEY, first));
+
+        // store, then record
+        assert_eq!(cache.add(KEY, VALUE), Some(true));
+        assert!(cache.sync_mtime(KEY, first));
+
+        // same file
+        assert!(cache.sync_mtime(KEY, first));
+
+        // file was replaced
+        assert!(!cache.sync_mtime(KEY, second));
     }
 
     /// Make sure that the scanner ticks at
@@ -412,7 +1162,7 @@ mod tests {
         let cache = scanning().await;
 
         // store
-        assert!(cache.add(KEY, VALUE));
+        assert_eq!(cache.add(KEY, VALUE), Some(true));
 
         // get again so that scanner timing
         // doesn't align w expiration
@@ -451,7 +1201,7 @@ mod tests {
         let cache = scanning().await;
 
         // store
-        assert!(cache.add(KEY, VALUE));
+        assert_eq!(cache.add(KEY, VALUE), Some(true));
 
         // make sure we don't expire early
         advance_clock_async(6500).await;
@@ -472,7 +1222,7 @@ mod tests {
 
         // store, get
         let added_at = MockSystemTime::now();
-        assert!(cache.add(KEY, VALUE));
+        assert_eq!(cache.add(KEY, VALUE), Some(true));
         assert_eq!(cache.get(KEY), Some(VALUE));
 
         // get after delay
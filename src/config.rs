@@ -1,7 +1,7 @@
 use std::{path::PathBuf, time::Duration};
 
 use serde::Deserialize;
-use serde_with::{DisplayFromStr, DurationSeconds, serde_as};
+use serde_with::{DisplayFromStr, DurationMilliSeconds, DurationSeconds, OneOrMany, serde_as};
 use tracing_subscriber::filter::LevelFilter;
 
 #[derive(Deserialize)]
@@ -11,6 +11,16 @@ pub struct Config {
     pub disk: DiskConfig,
     pub http: HttpConfig,
     pub logger: LoggerConfig,
+    #[serde(default)]
+    pub runtime: RuntimeConfig,
+}
+
+fn default_max_name_attempts() -> u32 {
+    8
+}
+
+fn default_upload_size_buckets() -> Vec<u64> {
+    vec![1 << 10, 64 << 10, 1 << 20, 16 << 20, 256 << 20]
 }
 
 fn default_motd() -> String {
@@ -23,30 +33,127 @@ pub struct EngineConfig {
     /// The url that the instance of breeze is meant to be accessed from.
     ///
     /// ex: `https://picture.wtf` would generate links like `https://picture.wtf/p/abcdef.png`
+    ///
+    /// If this is empty, links are relative (like `/p/abcdef.png`),
+    /// so one instance can be reached from several hostnames.
+    #[serde(default)]
     pub base_url: String,
 
     /// Authentication key for new uploads, will be required if this is specified. (optional)
     #[serde(default)]
     pub upload_key: String,
 
-    /// Secret key to use when generating or verifying deletion tokens.
+    /// Whether rejected uploads should be told if their upload key
+    /// was missing or wrong. Off by default, since it gives a little
+    /// away to anyone guessing at keys.
+    #[serde(default)]
+    pub verbose_auth_errors: bool,
+
+    /// Authentication key for admin endpoints (optional)
+    ///
+    /// If this is not specified, admin endpoints are disabled.
+    #[serde(default)]
+    pub admin_key: String,
+
+    /// Whether `/stats` can be viewed without the admin key
+    #[serde(default)]
+    pub public_stats: bool,
+
+    /// Upper bounds (in bytes) of the buckets upload sizes are counted in
+    /// for `/stats`. Anything bigger than all of them is counted in one more bucket
+    #[serde(default = "default_upload_size_buckets")]
+    pub upload_size_buckets: Vec<u64>,
+
+    /// Secret keys to use when generating or verifying deletion tokens.
+    /// The first signs new tokens, and all of them are accepted when
+    /// verifying, so the secret can be rotated without breaking old ones.
     /// Leave blank to disable.
     ///
-    /// If this secret is leaked, anyone can delete any file. Be careful!!!
-    pub deletion_secret: Option<String>,
+    /// If one of these secrets is leaked, anyone can delete any file. Be careful!!!
+    #[serde_as(as = "OneOrMany<_>")]
+    #[serde(default)]
+    pub deletion_secret: Vec<String>,
+
+    /// Whether the deletion hash should sample the end of uploads too,
+    /// not just the start.
+    ///
+    /// Changing this invalidates every deletion URL given out before!
+    #[serde(default)]
+    pub deletion_hash_tail: bool,
+
+    /// Uploads smaller than this (in bytes) have all of their data used
+    /// for the deletion hash, instead of just a sample (optional)
+    ///
+    /// Changing this invalidates deletion URLs for uploads
+    /// between 32KiB and the old or new length!
+    pub full_hash_below: Option<u64>,
+
+    /// Rate limits for deletion attempts, per client IP
+    #[serde(default)]
+    pub deletion_limit: DeletionLimitConfig,
+
+    /// File to append a JSON line to for every upload and deletion (optional)
+    pub audit_log: Option<PathBuf>,
 
     /// Maximum size of an upload that will be accepted.
     /// Files above this size can not be uploaded.
     pub max_upload_len: Option<u64>,
 
+    /// Minimum size of an upload that will be accepted.
+    /// Files below this size can not be uploaded.
+    pub min_upload_len: Option<u64>,
+
     /// Maximum lifetime of a temporary upload
     #[serde_as(as = "DurationSeconds")]
     pub max_temp_lifetime: Duration,
 
+    /// Longest random delay added to "not found" responses from viewing
+    /// and deleting, so response times don't show which names exist
+    /// (in milliseconds, optional)
+    #[serde_as(as = "Option<DurationMilliSeconds>")]
+    #[serde(default)]
+    pub not_found_jitter: Option<Duration>,
+
     /// Maximum length (in bytes) a file can be before the server will
     /// decide not to remove its EXIF data.
     pub max_strip_len: u64,
 
+    /// Whether image uploads should be rejected if their EXIF data
+    /// needed removing but it couldn't be done
+    #[serde(default)]
+    pub require_exif_strip: bool,
+
+    /// How many times to try generating an unused name for an upload
+    /// before giving up on it.
+    #[serde(default = "default_max_name_attempts")]
+    pub max_name_attempts: u32,
+
+    /// Which characters generated upload names are made of
+    #[serde(default)]
+    pub name_alphabet: NameAlphabet,
+
+    /// Whether file extensions should be lowercased and have
+    /// common aliases replaced (ex: `JPEG` -> `jpg`)
+    #[serde(default)]
+    pub normalize_extensions: bool,
+
+    /// Whether cached uploads should be checked against the file on disk
+    /// before being served, so files replaced out-of-band aren't served stale.
+    ///
+    /// This costs a `stat` call per cached view.
+    #[serde(default)]
+    pub verify_cache_mtime: bool,
+
+    /// Whether uploading and viewing should be rejected (503)
+    /// until the server has finished starting up.
+    #[serde(default)]
+    pub wait_for_ready: bool,
+
+    /// Whether temporary uploads should be included in
+    /// the motd's `%uplcount%` placeholder.
+    #[serde(default)]
+    pub count_temp_uploads: bool,
+
     /// Motd displayed when the server's index page is visited.
     ///
     /// This isn't explicitly engine-related but the engine is what gets passed to routes,
@@ -55,10 +162,95 @@ pub struct EngineConfig {
     pub motd: String,
 }
 
+/// Characters that generated upload names are made of
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NameAlphabet {
+    /// Upper and lowercase letters, and digits
+    #[default]
+    Alphanumeric,
+
+    /// Lowercase letters and digits, without ones that are easily
+    /// mixed up when read aloud or off a screen (`i`, `l`, `o` and `u`).
+    /// This is Crockford's base32 alphabet, lowercased
+    Unambiguous,
+}
+
+fn default_limit_window() -> Duration {
+    Duration::from_secs(60)
+}
+
+#[serde_as]
+#[derive(Deserialize)]
+pub struct DeletionLimitConfig {
+    /// How many deletion attempts a client can make per window (optional)
+    pub attempts: Option<u32>,
+
+    /// How many failed deletion attempts a client can make per window (optional)
+    ///
+    /// This should be lower than `attempts`, since failures
+    /// are what guessing at deletion URLs looks like.
+    pub failures: Option<u32>,
+
+    /// How long a rate limiting window lasts (in seconds)
+    #[serde_as(as = "DurationSeconds")]
+    #[serde(default = "default_limit_window")]
+    pub window: Duration,
+}
+
+impl Default for DeletionLimitConfig {
+    fn default() -> Self {
+        Self {
+            attempts: None,
+            failures: None,
+            window: default_limit_window(),
+        }
+    }
+}
+
+fn default_read_buffer() -> usize {
+    256 * 1024
+}
+
 #[derive(Deserialize, Clone)]
 pub struct DiskConfig {
     /// Location on disk the uploads are to be saved to
     pub save_path: PathBuf,
+
+    /// Location on disk temporary uploads are to be saved to (optional)
+    ///
+    /// If this is not specified, `save_path` is used.
+    pub temp_path: Option<PathBuf>,
+
+    /// Maximum number of uploads that can be saved to disk (optional)
+    pub max_files: Option<usize>,
+
+    /// Maximum number of uploads that can be streamed from disk at once (optional)
+    ///
+    /// Views past this wait for a stream to finish. Cached uploads skip it.
+    pub max_concurrent_reads: Option<usize>,
+
+    /// How much of an upload is read from disk at a time
+    /// when it's streamed to a client (in bytes)
+    #[serde(default = "default_read_buffer")]
+    pub read_buffer: usize,
+
+    /// Whether uploads should be written with direct I/O, skipping the page cache.
+    /// Needs the `direct-io` feature, and only works on Linux
+    #[serde(default)]
+    pub direct_io: bool,
+}
+
+fn default_tombstones() -> usize {
+    10_000
+}
+
+fn default_tombstone_lifetime() -> Duration {
+    Duration::from_secs(24 * 60 * 60)
+}
+
+fn default_large_promote_after() -> u32 {
+    10
 }
 
 #[serde_as]
@@ -68,9 +260,14 @@ pub struct CacheConfig {
     /// before it skips cache (in bytes)
     pub max_length: usize,
 
-    /// The amount of time a file can last inside the cache (in seconds)
+    /// How long a permanent upload stays cached after it was last viewed,
+    /// unless it was given its own time with `cachefor` (in seconds)
+    ///
+    /// Temporary uploads always expire with their own lifetime,
+    /// so this never applies to them.
     #[serde_as(as = "DurationSeconds")]
-    pub upload_lifetime: Duration,
+    #[serde(alias = "upload_lifetime")]
+    pub default_residency: Duration,
 
     /// How often the cache is to be scanned for
     /// expired entries (in seconds)
@@ -79,12 +276,193 @@ pub struct CacheConfig {
 
     /// How much memory the cache is allowed to use (in bytes)
     pub mem_capacity: usize,
+
+    /// Whether uploads should only be cached if there's free room for them,
+    /// instead of evicting older entries to make some.
+    ///
+    /// Stops a stream of uploads from constantly pushing each other out.
+    #[serde(default)]
+    pub avoid_thrash: bool,
+
+    /// Whether temporary uploads are rejected when the cache is full,
+    /// instead of evicting uploads that haven't expired to make room.
+    ///
+    /// Temporary uploads only live in the cache, so this keeps
+    /// the ones already there from being lost to new ones.
+    #[serde(default)]
+    pub temp_never_evicts: bool,
+
+    /// File extensions that can be cached, like `png` (optional)
+    ///
+    /// If this is empty, uploads of any type can be.
+    #[serde(default)]
+    pub cacheable_types: Vec<String>,
+
+    /// File extensions that are never cached, like `mp4` (optional)
+    #[serde(default)]
+    pub excluded_types: Vec<String>,
+
+    /// How many expired temporary uploads are remembered, so viewing them
+    /// gets 410 Gone instead of 404 Not Found. 0 disables this
+    #[serde(default = "default_tombstones")]
+    pub tombstones: usize,
+
+    /// How long an expired temporary upload is remembered for (in seconds)
+    #[serde_as(as = "DurationSeconds")]
+    #[serde(default = "default_tombstone_lifetime")]
+    pub tombstone_lifetime: Duration,
+
+    /// The maximum length (in bytes) an upload over `max_length` can be
+    /// and still be cached once it's popular enough (optional)
+    ///
+    /// If it is not set, uploads over `max_length` are always streamed from disk.
+    pub large_promote_max: Option<usize>,
+
+    /// How many times an upload over `max_length` has to be viewed
+    /// between two cache scans before it's cached
+    #[serde(default = "default_large_promote_after")]
+    pub large_promote_after: u32,
+}
+
+fn default_enable() -> bool {
+    true
+}
+
+fn default_new_route() -> String {
+    "/new".to_string()
+}
+
+fn default_view_route() -> String {
+    "/p".to_string()
+}
+
+fn default_delete_route() -> String {
+    "/del".to_string()
+}
+
+fn default_static_route() -> String {
+    "/static".to_string()
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RoutesConfig {
+    /// Path that new uploads are sent to
+    #[serde(default = "default_new_route")]
+    pub new: String,
+
+    /// Path prefix that uploads are viewed under
+    ///
+    /// ex: `/f` would serve uploads at `/f/abcdef.png`
+    #[serde(default = "default_view_route")]
+    pub view: String,
+
+    /// Path that deletion URLs point to
+    #[serde(default = "default_delete_route")]
+    pub delete: String,
+
+    /// Path prefix that `http.static_dir` is served under
+    #[serde(default = "default_static_route")]
+    pub static_files: String,
+}
+
+impl Default for RoutesConfig {
+    fn default() -> Self {
+        Self {
+            new: default_new_route(),
+            view: default_view_route(),
+            delete: default_delete_route(),
+            static_files: default_static_route(),
+        }
+    }
+}
+
+/// What to do with a request that asks for too many byte ranges
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ExcessRanges {
+    /// Reject it with 416 Range Not Satisfiable
+    #[default]
+    Reject,
+
+    /// Ignore the ranges and send the whole upload
+    Full,
+}
+
+fn default_header_read_timeout() -> Duration {
+    Duration::from_secs(30)
 }
 
+#[serde_as]
 #[derive(Deserialize)]
 pub struct HttpConfig {
     /// The IP address the HTTP server should listen on
     pub listen_on: String,
+
+    /// Path to an icon file to serve at /favicon.ico (optional)
+    pub favicon: Option<PathBuf>,
+
+    /// Whether the index page (`/`) should be served
+    #[serde(default = "default_enable")]
+    pub enable_index: bool,
+
+    /// Whether `/robots.txt` should be served
+    #[serde(default = "default_enable")]
+    pub enable_robots: bool,
+
+    /// Paths to serve the main routes on
+    #[serde(default)]
+    pub routes: RoutesConfig,
+
+    /// How many downloads a single IP can have going at once (optional)
+    pub max_downloads_per_ip: Option<usize>,
+
+    /// Sites allowed to embed uploads, by host (like `example.com`,
+    /// or `*.example.com` for its subdomains).
+    /// If this is empty, any site can.
+    #[serde(default)]
+    pub allowed_referrers: Vec<String>,
+
+    /// Whether uploads can be viewed without a `Referer` header
+    /// while `allowed_referrers` is set, like when opening a link directly
+    #[serde(default = "default_enable")]
+    pub allow_no_referrer: bool,
+
+    /// How long a client has to send a request's headers (in seconds).
+    /// This is also the keep-alive idle timeout, since the wait for the
+    /// next request on a kept-alive connection counts too.
+    /// Connections that take longer are closed. 0 disables this.
+    ///
+    /// Only HTTP/1 connections are covered, HTTP/2 has no equivalent
+    #[serde_as(as = "DurationSeconds")]
+    #[serde(default = "default_header_read_timeout")]
+    pub header_read_timeout: Duration,
+
+    /// How many connections can be open at once (optional)
+    ///
+    /// Once this many are, new ones wait to be accepted
+    /// until another closes.
+    pub max_connections: Option<usize>,
+
+    /// The most byte ranges a single view request can ask for (optional)
+    pub max_ranges_per_request: Option<usize>,
+
+    /// What to do with view requests that ask for more ranges than that
+    #[serde(default)]
+    pub excess_ranges: ExcessRanges,
+
+    /// Value to send in the `Server` header on every response (optional)
+    ///
+    /// If this is empty, the header is removed instead.
+    pub server_header: Option<String>,
+
+    /// Directory of static files (like a frontend) to serve
+    /// under `routes.static_files` (optional)
+    pub static_dir: Option<PathBuf>,
+
+    /// Whether uploads can also be stored with `PUT` and removed
+    /// with `DELETE` on the view path, like a (very) simple WebDAV server
+    #[serde(default)]
+    pub webdav: bool,
 }
 
 fn default_level_filter() -> LevelFilter {
@@ -101,3 +479,14 @@ pub struct LoggerConfig {
     #[serde(default = "default_level_filter")]
     pub level: LevelFilter,
 }
+
+#[derive(Deserialize, Default)]
+pub struct RuntimeConfig {
+    /// Number of worker threads the async runtime should use.
+    /// This defaults to the number of CPU cores if not specified.
+    pub worker_threads: Option<usize>,
+
+    /// Maximum number of threads the runtime may spawn for blocking tasks.
+    /// This defaults to tokio's default (512) if not specified.
+    pub max_blocking_threads: Option<usize>,
+}
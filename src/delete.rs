@@ -1,81 +1,285 @@
-use std::sync::{Arc, atomic::Ordering};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, atomic::Ordering},
+};
 
-use axum::extract::{Query, State};
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    response::{Html, IntoResponse, Response},
+};
 use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
 use bytes::{Buf, BytesMut};
-use hmac::Mac;
-use http::StatusCode;
+use http::{HeaderMap, StatusCode, header};
 use serde::Deserialize;
 
-use crate::engine::{Engine, update_hmac};
+use crate::{audit::AuditAction, engine::Engine, feature::Feature};
 
 #[derive(Deserialize)]
 pub struct DeleteRequest {
     name: String,
-    hash: String,
-    hmac: String,
+    hash: Option<String>,
+    hmac: Option<String>,
+    confirm: Option<String>,
+    check: Option<String>,
 }
 
+impl DeleteRequest {
+    /// Whether the client only wants to check the token (`check=1`).
+    fn is_check(&self) -> bool {
+        self.check
+            .as_deref()
+            .is_some_and(|c| c == "1" || c == "true")
+    }
+
+    /// Use the deletion token from the request headers, if one was sent.
+    /// It's preferred over the query params, which can end up in access logs
+    fn with_header_token(mut self, headers: &HeaderMap) -> Self {
+        if let Some((hash, hmac)) = token_from_header(headers) {
+            self.hash = Some(hash);
+            self.hmac = Some(hmac);
+        }
+        self
+    }
+}
+
+/// Page shown to browsers before deleting anything.
+/// The form has no action, so it posts back to the same URL (query included).
+const CONFIRM_HTML: &str = concat!(
+    "<!DOCTYPE html>\n",
+    "<html><head><meta charset=\"utf-8\"><title>Delete upload?</title></head><body>\n",
+    "<p>Are you sure you want to delete this upload? This can't be undone.</p>\n",
+    "<form method=\"post\"><button type=\"submit\">Delete</button></form>\n",
+    "</body></html>\n"
+);
+
+/// GET request handler for the /del path.
+///
+/// Browsers are sent a confirmation page instead of deleting right away,
+/// so link previewers can't delete uploads just by following the URL.
+/// Other clients (or requests with `confirm=1`) delete immediately.
+/// With `check=1`, the token is only verified and nothing is deleted.
 pub async fn delete(
     State(engine): State<Arc<Engine>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(req): Query<DeleteRequest>,
+) -> Response {
+    let wants_html = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/html"));
+    let confirmed = req
+        .confirm
+        .as_deref()
+        .is_some_and(|c| c == "1" || c == "true");
+
+    let dry_run = req.is_check();
+    let req = req.with_header_token(&headers);
+
+    if wants_html && !confirmed && !dry_run && engine.deletion_hmac.is_some() {
+        return Html(CONFIRM_HTML).into_response();
+    }
+
+    try_delete_limited(&engine, addr.ip(), req, dry_run)
+        .await
+        .into_response()
+}
+
+/// HEAD request handler for the /del path.
+/// Verifies the token and that the upload exists, but never deletes.
+pub async fn delete_check(
+    State(engine): State<Arc<Engine>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(req): Query<DeleteRequest>,
+) -> StatusCode {
+    let req = req.with_header_token(&headers);
+    try_delete_limited(&engine, addr.ip(), req, true).await.0
+}
+
+/// POST request handler for the /del path.
+/// This is what the confirmation page submits to, and always deletes.
+pub async fn delete_confirmed(
+    State(engine): State<Arc<Engine>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Query(req): Query<DeleteRequest>,
 ) -> (StatusCode, &'static str) {
-    let Some(mut hmac) = engine.deletion_hmac.clone() else {
-        return (StatusCode::CONFLICT, "Deletion is not enabled");
+    let dry_run = req.is_check();
+    let req = req.with_header_token(&headers);
+    try_delete_limited(&engine, addr.ip(), req, dry_run).await
+}
+
+/// Header a deletion token can be sent in, instead of the query string.
+/// Its value is `{hash}.{hmac}`, taken from the upload's deletion URL.
+/// The same value is also accepted as an `Authorization: Bearer` token
+pub const TOKEN_HEADER: &str = "Breeze-Deletion-Token";
+
+/// Read a deletion token (`hash` and `hmac`) from the request headers
+pub fn token_from_header(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = match headers.get(TOKEN_HEADER) {
+        Some(value) => value.to_str().ok()?,
+        None => {
+            let auth = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+            let (scheme, token) = auth.trim().split_once(' ')?;
+            if !scheme.eq_ignore_ascii_case("bearer") {
+                return None;
+            }
+            token
+        }
+    };
+    let (hash, hmac) = value.trim().split_once('.')?;
+
+    Some((hash.to_string(), hmac.to_string()))
+}
+
+/// DELETE request handler for the view path, if `http.webdav` is enabled.
+/// The deletion token is sent in the `Breeze-Deletion-Token` header
+/// (or as an `Authorization: Bearer` token).
+pub async fn delete_by_header(
+    State(engine): State<Arc<Engine>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(saved_name): Path<String>,
+    headers: HeaderMap,
+) -> (StatusCode, &'static str) {
+    if engine.deletion_hmac.is_none() {
+        return Feature::Deletion.disabled();
+    }
+
+    let Some((hash, hmac)) = token_from_header(&headers) else {
+        return (StatusCode::UNAUTHORIZED, "No deletion token was provided");
     };
 
+    let req = DeleteRequest {
+        name: saved_name,
+        hash: Some(hash),
+        hmac: Some(hmac),
+        confirm: None,
+        check: None,
+    };
+    try_delete_limited(&engine, addr.ip(), req, false).await
+}
+
+/// Apply deletion rate limits to a client, then try to delete.
+async fn try_delete_limited(
+    engine: &Engine,
+    ip: IpAddr,
+    req: DeleteRequest,
+    dry_run: bool,
+) -> (StatusCode, &'static str) {
+    // check if the client is over either limit
+    let over_attempts = engine
+        .deletion_attempts
+        .as_ref()
+        .is_some_and(|l| !l.hit(ip));
+    let over_failures = engine
+        .deletion_failures
+        .as_ref()
+        .is_some_and(|l| l.is_limited(ip));
+    if over_attempts || over_failures {
+        return (StatusCode::TOO_MANY_REQUESTS, "Too many deletion attempts");
+    }
+
+    let res = try_delete(engine, ip, req, dry_run).await;
+
+    // failed verification counts towards the tighter limit
+    if res.0 == StatusCode::BAD_REQUEST
+        && let Some(ref limiter) = engine.deletion_failures
+    {
+        limiter.hit(ip);
+    }
+
+    res
+}
+
+/// Verify a deletion token (the `hash` and `hmac` of a deletion URL),
+/// and that it's for the upload as it is now.
+///
+/// Deletion URLs need to be enabled, check that first.
+pub async fn check_token(
+    engine: &Engine,
+    saved_name: &str,
+    hash: &str,
+    hmac: &str,
+) -> Result<(), (StatusCode, &'static str)> {
     // -- decode provided data
 
     // decode user-given hmac
-    let Ok(provided_hmac) = BASE64_URL_SAFE_NO_PAD.decode(req.hmac) else {
-        return (StatusCode::BAD_REQUEST, "Could not decode hmac");
+    let Ok(provided_hmac) = BASE64_URL_SAFE_NO_PAD.decode(hmac) else {
+        return Err((StatusCode::BAD_REQUEST, "Could not decode hmac"));
     };
 
     // decode hash from base64
     let Ok(mut provided_hash_data) = BASE64_URL_SAFE_NO_PAD
-        .decode(req.hash)
+        .decode(hash)
         .map(|v| BytesMut::from(&v[..]))
     else {
-        return (StatusCode::BAD_REQUEST, "Could not decode partial hash");
+        return Err((StatusCode::BAD_REQUEST, "Could not decode partial hash"));
     };
     // read hash
     if provided_hash_data.len() != 16 {
-        return (StatusCode::BAD_REQUEST, "Partial hash length is invalid");
+        return Err((StatusCode::BAD_REQUEST, "Partial hash length is invalid"));
     }
     let provided_hash = provided_hash_data.get_u128();
 
     // -- verify it
 
-    // check if info is valid
-    let is_hmac_valid = {
-        // update hmad
-        update_hmac(&mut hmac, &req.name, provided_hash);
-        // verify..
-        hmac.verify_slice(&provided_hmac).is_ok()
-    };
+    // check if info is valid (with any of the secrets)
+    let is_hmac_valid = engine.verify_deletion_hmac(saved_name, provided_hash, &provided_hmac);
     if !is_hmac_valid {
-        return (StatusCode::BAD_REQUEST, "Hmac is invalid");
+        return Err((StatusCode::BAD_REQUEST, "Hmac is invalid"));
     }
 
     // -- ensure hash matches
 
     // okay, now check if we compute the same hash as the req
     // this makes sure it's (probably) the same file
-    let actual_hash = match engine.get_hash(&req.name).await {
+    let actual_hash = match engine.get_hash(saved_name).await {
         Ok(Some(h)) => h,
-        Ok(None) => return (StatusCode::NOT_FOUND, "File not found"),
+        Ok(None) => {
+            engine.not_found_jitter().await;
+            return Err((StatusCode::NOT_FOUND, "File not found"));
+        }
         Err(err) => {
             tracing::error!(%err, "failed to get hash");
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error!!");
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, "Internal server error!!"));
         }
     };
     // compare
     if provided_hash != actual_hash {
-        return (StatusCode::BAD_REQUEST, "Partial hash did not match");
+        return Err((StatusCode::BAD_REQUEST, "Partial hash did not match"));
+    }
+
+    Ok(())
+}
+
+/// Verify a deletion request and delete the upload if it's valid.
+/// If `dry_run` is set, stop right before deleting.
+async fn try_delete(
+    engine: &Engine,
+    ip: IpAddr,
+    req: DeleteRequest,
+    dry_run: bool,
+) -> (StatusCode, &'static str) {
+    if engine.deletion_hmac.is_none() {
+        return Feature::Deletion.disabled();
+    }
+
+    // the token can come from the query string or the headers
+    let (Some(hash), Some(hmac)) = (&req.hash, &req.hmac) else {
+        return (StatusCode::UNAUTHORIZED, "No deletion token was provided");
+    };
+
+    if let Err(rejection) = check_token(engine, &req.name, hash, hmac).await {
+        return rejection;
     }
 
     // -- delete file
 
+    if dry_run {
+        return (StatusCode::OK, "Deletion token is valid");
+    }
+
     // everything seems okay so try to delete
     if let Err(err) = engine.remove(&req.name).await {
         tracing::error!(%err, "failed to delete upload");
@@ -85,5 +289,52 @@ pub async fn delete(
     // decrement upload count
     engine.upl_count.fetch_sub(1, Ordering::Relaxed);
 
+    engine.audit(AuditAction::Delete, &req.name, None, Some(ip));
+
     (StatusCode::OK, "Deleted successfully!")
 }
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, HeaderValue, header};
+
+    use super::{TOKEN_HEADER, token_from_header};
+
+    /// Make sure deletion tokens are read from either header,
+    /// preferring the dedicated one
+    #[test]
+    fn header_tokens() {
+        let token = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for &(name, value) in pairs {
+                headers.insert(name, HeaderValue::from_static(value));
+            }
+            token_from_header(&headers)
+        };
+        let pair = |hash: &str, hmac: &str| Some((hash.to_string(), hmac.to_string()));
+
+        assert_eq!(token(&[(TOKEN_HEADER, "abc.def")]), pair("abc", "def"));
+        assert_eq!(
+            token(&[(header::AUTHORIZATION.as_str(), "Bearer abc.def")]),
+            pair("abc", "def")
+        );
+        assert_eq!(
+            token(&[(header::AUTHORIZATION.as_str(), "bearer  abc.def ")]),
+            pair("abc", "def")
+        );
+        assert_eq!(
+            token(&[
+                (TOKEN_HEADER, "abc.def"),
+                (header::AUTHORIZATION.as_str(), "Bearer ghi.jkl")
+            ]),
+            pair("abc", "def")
+        );
+
+        assert_eq!(token(&[]), None);
+        assert_eq!(
+            token(&[(header::AUTHORIZATION.as_str(), "Basic YWJj")]),
+            None
+        );
+        assert_eq!(token(&[(TOKEN_HEADER, "abcdef")]), None);
+    }
+}
@@ -1,105 +1,397 @@
-use std::path::{Path, PathBuf};
+use std::{
+    fs::Metadata,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::SystemTime,
+};
 
 use bytes::Bytes;
 use tokio::{
-    fs::File,
+    fs::{File, OpenOptions},
     io::{self, AsyncWriteExt},
-    sync::mpsc,
+    sync::{OwnedSemaphorePermit, Semaphore, mpsc, oneshot},
 };
+use tokio_util::task::TaskTracker;
 
 use crate::config;
 
+/// A summary of what's saved to disk
+#[derive(Clone, Copy, Default)]
+pub struct DiskUsage {
+    /// Total length of every upload
+    pub length: u64,
+
+    /// Modification time of the oldest upload
+    pub oldest: Option<SystemTime>,
+
+    /// Modification time of the newest upload
+    pub newest: Option<SystemTime>,
+}
+
+/// Extensions of files that sit alongside uploads without being one,
+/// like partial writes or metadata sidecars
+const NON_UPLOAD_EXTS: &[&str] = &["part", "meta", "tmp"];
+
+/// Name of the file written to check the save path can be written to.
+/// It's hidden so it is never mistaken for an upload
+const PROBE_NAME: &str = ".breeze-probe";
+
+/// Returns if a file in the save path looks like an actual upload
+pub fn is_upload(file_name: &str) -> bool {
+    if file_name.starts_with('.') {
+        return false;
+    }
+
+    Path::new(file_name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_none_or(|e| !NON_UPLOAD_EXTS.contains(&e))
+}
+
 /// Provides an API to access the disk file store
 /// like we access the cache.
 pub struct Disk {
     cfg: config::DiskConfig,
+
+    /// Whether the save path could be written to, as of the last write
+    writable: Arc<AtomicBool>,
+
+    /// Limits how many uploads are streamed from disk at once (if configured)
+    reads: Option<Arc<Semaphore>>,
+
+    /// Background tasks saving uploads, so they can be waited on
+    saves: TaskTracker,
 }
 
 impl Disk {
     pub fn with_config(cfg: config::DiskConfig) -> Self {
-        Self { cfg }
+        let reads = cfg
+            .max_concurrent_reads
+            .map(|n| Arc::new(Semaphore::new(n)));
+
+        Self {
+            cfg,
+            writable: Arc::new(AtomicBool::new(true)),
+            reads,
+            saves: TaskTracker::new(),
+        }
+    }
+
+    /// Wait for every save started so far to finish writing
+    pub async fn wait_for_saves(&self) {
+        // the tracker only finishes waiting once it's closed,
+        // and reopening it right after lets new saves be tracked as before
+        self.saves.close();
+        self.saves.wait().await;
+        self.saves.reopen();
+    }
+
+    /// Wait for a turn to stream an upload from disk, if that's limited.
+    /// The permit should be held until the stream is done
+    pub async fn read_permit(&self) -> Option<OwnedSemaphorePermit> {
+        let reads = self.reads.clone()?;
+
+        // the semaphore is never closed
+        reads.acquire_owned().await.ok()
     }
 
-    /// Counts the number of files saved to disk we have
+    /// How much of an upload should be read at a time when streaming it
+    pub fn read_buffer(&self) -> usize {
+        self.cfg.read_buffer
+    }
+
+    /// Returns if the save path could be written to, as of the last write.
+    /// This doesn't touch the disk, use [`Disk::check_writable`] for that
+    #[inline]
+    pub fn is_writable(&self) -> bool {
+        self.writable.load(Ordering::Relaxed)
+    }
+
+    /// Check the save path can still be written to, by creating
+    /// and removing a small file in it.
+    ///
+    /// Returns: whether it could be
+    pub async fn check_writable(&self) -> bool {
+        let p = self.cfg.save_path.join(PROBE_NAME);
+
+        let res = async {
+            let mut file = File::create(&p).await?;
+            file.write_all(b"breeze").await?;
+            file.flush().await?;
+            drop(file);
+            tokio::fs::remove_file(&p).await
+        };
+
+        match res.await {
+            Ok(()) => {
+                if !self.writable.swap(true, Ordering::Relaxed) {
+                    tracing::info!("save path can be written to again");
+                }
+                true
+            }
+            Err(err) => {
+                mark_unwritable(&self.writable, &err);
+                false
+            }
+        }
+    }
+
+    /// Counts the number of uploads saved to disk we have
+    ///
+    /// Directories and anything that isn't an upload are skipped.
     pub fn count(&self) -> io::Result<usize> {
         std::fs::read_dir(&self.cfg.save_path)?.try_fold(0, |acc, x| {
-            Ok(if x?.file_type()?.is_file() {
-                acc + 1
-            } else {
-                acc
-            })
+            let x = x?;
+            Ok(
+                if x.file_type()?.is_file() && x.file_name().to_str().is_some_and(is_upload) {
+                    acc + 1
+                } else {
+                    acc
+                },
+            )
         })
     }
 
+    /// Adds up the length of every file saved to disk,
+    /// and finds the oldest and newest of them
+    pub async fn usage(&self) -> io::Result<DiskUsage> {
+        let mut usage = DiskUsage::default();
+
+        let mut dir = tokio::fs::read_dir(&self.cfg.save_path).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() || !entry.file_name().to_str().is_some_and(is_upload) {
+                continue;
+            }
+
+            usage.length += metadata.len();
+
+            if let Ok(mtime) = metadata.modified() {
+                usage.oldest = Some(usage.oldest.map_or(mtime, |t| t.min(mtime)));
+                usage.newest = Some(usage.newest.map_or(mtime, |t| t.max(mtime)));
+            }
+        }
+
+        Ok(usage)
+    }
+
+    /// Lists every upload saved to disk, with when it was last modified
+    pub async fn list(&self) -> io::Result<Vec<(String, SystemTime)>> {
+        let mut uploads = Vec::new();
+
+        let mut dir = tokio::fs::read_dir(&self.cfg.save_path).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() {
+                continue;
+            }
+
+            // names we can't use in a url can't be uploads
+            let Ok(saved_name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if !is_upload(&saved_name) {
+                continue;
+            }
+
+            uploads.push((saved_name, metadata.modified()?));
+        }
+
+        Ok(uploads)
+    }
+
+    /// Returns if another upload can be saved to disk,
+    /// given how many are already saved
+    #[inline]
+    pub fn will_fit(&self, count: usize) -> bool {
+        self.cfg.max_files.is_none_or(|max| count < max)
+    }
+
+    /// The directory temporary uploads are saved to.
+    /// This falls back to `save_path` if `temp_path` isn't set.
+    fn temp_dir(&self) -> &Path {
+        self.cfg.temp_path.as_deref().unwrap_or(&self.cfg.save_path)
+    }
+
     /// Formats the path on disk for a `saved_name`.
-    fn path_for(&self, saved_name: &str) -> PathBuf {
+    fn path_for(&self, saved_name: &str, temporary: bool) -> PathBuf {
         // try to prevent path traversal by ignoring everything except the file name
         let name = Path::new(saved_name).file_name().unwrap_or_default();
 
-        let mut p: PathBuf = self.cfg.save_path.clone();
+        let mut p: PathBuf = if temporary {
+            self.temp_dir().to_path_buf()
+        } else {
+            self.cfg.save_path.clone()
+        };
         p.push(name);
 
         p
     }
 
+    /// Every path on disk a `saved_name` could be at.
+    /// The permanent path comes first.
+    fn paths_for(&self, saved_name: &str) -> Vec<PathBuf> {
+        let mut paths = vec![self.path_for(saved_name, false)];
+
+        if self.temp_dir() != self.cfg.save_path {
+            paths.push(self.path_for(saved_name, true));
+        }
+
+        paths
+    }
+
     /// Try to open a file on disk, and if we didn't find it,
     /// then return [`None`].
     pub async fn open(&self, saved_name: &str) -> io::Result<Option<File>> {
-        let p = self.path_for(saved_name);
-
-        match File::open(p).await {
-            Ok(f) => Ok(Some(f)),
-            Err(e) => match e.kind() {
-                io::ErrorKind::NotFound => Ok(None),
-                _ => Err(e)?, // some other error, send it back
-            },
+        for p in self.paths_for(saved_name) {
+            match File::open(p).await {
+                Ok(f) => return Ok(Some(f)),
+                Err(e) => match e.kind() {
+                    io::ErrorKind::NotFound => continue,
+                    _ => Err(e)?, // some other error, send it back
+                },
+            }
         }
+
+        Ok(None)
     }
 
-    /// Get the size of an upload's file
-    pub async fn len(&self, f: &File) -> io::Result<u64> {
-        Ok(f.metadata().await?.len())
+    /// Get the metadata (size, modification time, etc.) of an upload's file
+    pub async fn metadata(&self, f: &File) -> io::Result<Metadata> {
+        f.metadata().await
+    }
+
+    /// Get the modification time of an upload's file,
+    /// or [`None`] if it isn't on disk.
+    pub async fn mtime(&self, saved_name: &str) -> io::Result<Option<SystemTime>> {
+        for p in self.paths_for(saved_name) {
+            match tokio::fs::metadata(p).await {
+                Ok(m) => return Ok(Some(m.modified()?)),
+                Err(e) => match e.kind() {
+                    io::ErrorKind::NotFound => continue,
+                    _ => Err(e)?,
+                },
+            }
+        }
+
+        Ok(None)
     }
 
     /// Remove an upload from disk.
     pub async fn remove(&self, saved_name: &str) -> io::Result<()> {
-        let p = self.path_for(saved_name);
+        for p in self.paths_for(saved_name) {
+            match tokio::fs::remove_file(p).await {
+                Ok(()) => return Ok(()),
+                Err(e) => match e.kind() {
+                    io::ErrorKind::NotFound => continue,
+                    _ => Err(e)?,
+                },
+            }
+        }
 
-        tokio::fs::remove_file(p).await
+        Err(io::ErrorKind::NotFound.into())
+    }
+
+    /// Create a new, empty upload file.
+    pub async fn create(&self, saved_name: &str) -> io::Result<()> {
+        let p = self.path_for(saved_name, false);
+
+        File::create(p).await?;
+
+        Ok(())
+    }
+
+    /// Append data to the end of an upload's file.
+    ///
+    /// Returns: the offset the data was written at,
+    /// or [`None`] if the file isn't on disk
+    pub async fn append(&self, saved_name: &str, data: &[u8]) -> io::Result<Option<u64>> {
+        let p = self.path_for(saved_name, false);
+
+        let mut file = match OpenOptions::new().append(true).open(p).await {
+            Ok(f) => f,
+            Err(e) => match e.kind() {
+                io::ErrorKind::NotFound => return Ok(None),
+                _ => Err(e)?,
+            },
+        };
+
+        let offset = file.metadata().await?.len();
+        file.write_all(data).await?;
+        file.flush().await?;
+
+        Ok(Some(offset))
+    }
+
+    /// Move an upload's file to a new name, replacing whatever was there.
+    pub async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        tokio::fs::rename(self.path_for(from, false), self.path_for(to, false)).await
     }
 
     /// Create a background I/O task
+    ///
+    /// Returns: a sender to pass it chunks with, and a receiver that
+    /// resolves once every chunk is written (or errors if that failed)
     pub fn start_save<
         Fut: Future + Send + 'static,
         F: FnOnce(io::Error) -> Fut + Send + 'static,
     >(
         &self,
         saved_name: &str,
+        temporary: bool,
         fail_callback: F,
-    ) -> mpsc::Sender<Bytes> {
+    ) -> (mpsc::Sender<Bytes>, oneshot::Receiver<()>) {
         // start a task that handles saving files to disk (we can save to cache/disk in parallel that way)
         // a large buffer size is chosen so uploads can be received quickly,
         // but with less possibility of running out of memory.
         // (thats probably only possible w very high link speed tho......)
         let (tx, mut rx): (mpsc::Sender<Bytes>, mpsc::Receiver<Bytes>) = mpsc::channel(30000);
+        let (done_tx, done_rx) = oneshot::channel();
 
-        let p = self.path_for(saved_name);
+        let p = self.path_for(saved_name, temporary);
+        let writable = self.writable.clone();
 
-        tokio::spawn(async move {
-            // create file to save upload to
-            let mut file = match File::create(p).await {
-                Ok(f) => f,
+        // create file to save upload to.
+        // this is done before returning, so a failed upload can't be removed
+        // before its file has been created (which would leave an empty file behind)
+        #[cfg(all(feature = "direct-io", target_os = "linux"))]
+        if self.cfg.direct_io {
+            let writer = match direct::DirectWriter::create(&p) {
+                Ok(w) => w,
                 Err(err) => {
                     tracing::error!(%err, "could not open file! make sure your upload path is valid");
-                    return;
+                    mark_unwritable(&self.writable, &err);
+                    return (tx, done_rx);
                 }
             };
 
+            direct::spawn_save(&self.saves, writer, rx, done_tx, async move |err| {
+                mark_unwritable(&writable, &err);
+                fail_callback(err).await;
+            });
+            return (tx, done_rx);
+        }
+
+        let mut file = match std::fs::File::create(p) {
+            Ok(f) => File::from_std(f),
+            Err(err) => {
+                // dropping the receiver makes sending chunks fail, so the upload fails too
+                tracing::error!(%err, "could not open file! make sure your upload path is valid");
+                mark_unwritable(&self.writable, &err);
+                return (tx, done_rx);
+            }
+        };
+
+        self.saves.spawn(async move {
             // receive chunks and save them to file
             while let Some(chunk) = rx.recv().await {
                 tracing::debug!(length = chunk.len(), "writing chunk to disk");
                 if let Err(err) = file.write_all(&chunk).await {
                     drop(rx);
+                    mark_unwritable(&writable, &err);
                     fail_callback(err).await;
                     return;
                 }
@@ -108,10 +400,271 @@ impl Disk {
             // flush to disk
             // this should catch "no space left on device" i hope...
             if let Err(err) = file.flush().await {
+                mark_unwritable(&writable, &err);
                 fail_callback(err).await;
+                return;
             }
+
+            // nobody has to be waiting for this
+            let _ = done_tx.send(());
         });
 
-        tx
+        (tx, done_rx)
+    }
+}
+
+/// Flag the save path as unwritable after a failed write,
+/// making noise the first time it happens
+fn mark_unwritable(writable: &AtomicBool, err: &io::Error) {
+    if writable.swap(false, Ordering::Relaxed) {
+        tracing::error!(%err, "save path can no longer be written to! uploads to disk will be refused");
+    }
+}
+
+/// Writing uploads with `O_DIRECT`, skipping the page cache.
+///
+/// Writes have to be made from aligned memory, at aligned offsets,
+/// in multiples of the block size. So chunks are gathered into an
+/// aligned buffer, and it's written out whenever it fills up.
+#[cfg(all(feature = "direct-io", target_os = "linux"))]
+mod direct {
+    use std::{
+        fs::{File, OpenOptions},
+        io::{self, Write},
+        os::unix::fs::OpenOptionsExt,
+        path::PathBuf,
+    };
+
+    use bytes::Bytes;
+    use tokio::sync::{mpsc, oneshot};
+    use tokio_util::task::TaskTracker;
+
+    /// What writes are aligned to. This covers the block size of most filesystems
+    const ALIGN: usize = 4096;
+
+    /// How much is gathered before writing it out
+    const BUF_LEN: usize = 1024 * 1024;
+
+    /// Writes a file in aligned blocks
+    pub(super) struct DirectWriter {
+        file: File,
+
+        /// Backing memory. Only `start..start + BUF_LEN` of it is used, which is aligned
+        storage: Vec<u8>,
+        start: usize,
+
+        /// How much of the buffer holds data
+        filled: usize,
+
+        /// How much has been written to the file so far
+        written: u64,
+    }
+
+    impl DirectWriter {
+        pub(super) fn create(path: &PathBuf) -> io::Result<Self> {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .custom_flags(libc::O_DIRECT)
+                .open(path)?;
+
+            // over-allocate so an aligned buffer fits somewhere inside
+            let storage = vec![0; BUF_LEN + ALIGN];
+            let start = storage.as_ptr().align_offset(ALIGN);
+
+            Ok(Self {
+                file,
+                storage,
+                start,
+                filled: 0,
+                written: 0,
+            })
+        }
+
+        pub(super) fn write(&mut self, mut data: &[u8]) -> io::Result<()> {
+            while !data.is_empty() {
+                let taking = (BUF_LEN - self.filled).min(data.len());
+                let at = self.start + self.filled;
+                self.storage[at..at + taking].copy_from_slice(&data[..taking]);
+                self.filled += taking;
+                data = &data[taking..];
+
+                if self.filled == BUF_LEN {
+                    self.file
+                        .write_all(&self.storage[self.start..self.start + BUF_LEN])?;
+                    self.written += BUF_LEN as u64;
+                    self.filled = 0;
+                }
+            }
+
+            Ok(())
+        }
+
+        /// Write out whatever is left over
+        pub(super) fn finish(mut self) -> io::Result<()> {
+            if self.filled == 0 {
+                return Ok(());
+            }
+
+            // the last write still has to be a whole number of blocks,
+            // so pad it out and then cut the file back down afterwards
+            let padded = self.filled.next_multiple_of(ALIGN);
+            let at = self.start + self.filled;
+            self.storage[at..self.start + padded].fill(0);
+            self.file
+                .write_all(&self.storage[self.start..self.start + padded])?;
+            self.file.set_len(self.written + self.filled as u64)
+        }
+    }
+
+    /// Save an upload on a blocking thread, with direct I/O
+    pub(super) fn spawn_save<Fut, F>(
+        saves: &TaskTracker,
+        mut writer: DirectWriter,
+        mut rx: mpsc::Receiver<Bytes>,
+        done: oneshot::Sender<()>,
+        fail_callback: F,
+    ) where
+        Fut: Future + Send + 'static,
+        F: FnOnce(io::Error) -> Fut + Send + 'static,
+    {
+        saves.spawn(async move {
+            let res = tokio::task::spawn_blocking(move || {
+                while let Some(chunk) = rx.blocking_recv() {
+                    tracing::debug!(length = chunk.len(), "writing chunk to disk (direct)");
+                    writer.write(&chunk)?;
+                }
+
+                writer.finish()
+            })
+            .await
+            .unwrap_or_else(|err| Err(io::Error::other(err)));
+
+            match res {
+                Ok(()) => {
+                    let _ = done.send(());
+                }
+                Err(err) => {
+                    fail_callback(err).await;
+                }
+            }
+        });
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::DirectWriter;
+
+        /// Make sure files come out the same
+        /// when they aren't a whole number of blocks
+        #[test]
+        fn unaligned_length() {
+            let path = std::env::temp_dir().join("breeze-direct-io-test");
+            let data: Vec<u8> = (0..3_000_000u32).map(|n| n as u8).collect();
+
+            let mut writer = DirectWriter::create(&path).unwrap();
+            for chunk in data.chunks(12345) {
+                writer.write(chunk).unwrap();
+            }
+            writer.finish().unwrap();
+
+            let written = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(written, data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Disk;
+    use crate::config::DiskConfig;
+
+    /// Make sure only actual uploads are counted
+    #[test]
+    fn count_only_uploads() {
+        let dir = std::env::temp_dir().join(format!("breeze-count-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("shard")).unwrap();
+        for name in [
+            "abcdef.png",
+            "ghijkl",
+            "mnopqr.png.part",
+            "abcdef.png.meta",
+            ".hidden",
+        ] {
+            std::fs::write(dir.join(name), b"hi").unwrap();
+        }
+
+        let disk = Disk::with_config(DiskConfig {
+            save_path: dir.clone(),
+            temp_path: None,
+            max_files: None,
+            max_concurrent_reads: None,
+            read_buffer: 4096,
+            direct_io: false,
+        });
+        let count = disk.count();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(count.unwrap(), 2);
+    }
+
+    /// Make sure a save path that goes away is noticed,
+    /// and so is it coming back
+    #[tokio::test]
+    async fn writable_check() {
+        let dir = std::env::temp_dir().join(format!("breeze-probe-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let disk = Disk::with_config(DiskConfig {
+            save_path: dir.clone(),
+            temp_path: None,
+            max_files: None,
+            max_concurrent_reads: None,
+            read_buffer: 4096,
+            direct_io: false,
+        });
+        assert!(disk.check_writable().await);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        std::fs::remove_dir(&dir).unwrap();
+        assert!(!disk.check_writable().await);
+        assert!(!disk.is_writable());
+
+        std::fs::create_dir(&dir).unwrap();
+        assert!(disk.check_writable().await);
+        assert!(disk.is_writable());
+
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    /// Make sure disk reads past the limit wait for a turn
+    #[tokio::test]
+    async fn read_permits() {
+        let disk = Disk::with_config(DiskConfig {
+            save_path: std::env::temp_dir(),
+            temp_path: None,
+            max_files: None,
+            max_concurrent_reads: Some(1),
+            read_buffer: 4096,
+            direct_io: false,
+        });
+
+        let first = disk.read_permit().await;
+        assert!(first.is_some());
+
+        let wait = Duration::from_millis(20);
+        assert!(
+            tokio::time::timeout(wait, disk.read_permit())
+                .await
+                .is_err()
+        );
+
+        drop(first);
+        let second = tokio::time::timeout(wait, disk.read_permit()).await;
+        assert!(second.unwrap().is_some());
     }
 }
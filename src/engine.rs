@@ -1,75 +1,367 @@
 use std::{
+    collections::BTreeMap,
+    fmt,
     io::SeekFrom,
+    net::IpAddr,
     ops::{Bound, RangeBounds},
     sync::{
         Arc,
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
     },
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
-use axum::body::BodyDataStream;
+use async_compression::tokio::bufread::{GzipDecoder, ZlibDecoder};
+use axum::body::{Body, BodyDataStream};
 use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use color_eyre::eyre::{self, WrapErr};
+use dashmap::{DashMap, mapref::entry::Entry};
 use hmac::Mac;
 use img_parts::{DynImage, ImageEXIF};
-use rand::distr::{Alphanumeric, SampleString};
+use rand::{
+    Rng,
+    distr::{Alphanumeric, SampleString},
+    seq::IndexedRandom,
+};
 use tokio::{
     fs::File,
     io::{AsyncReadExt, AsyncSeekExt},
+    sync::{Mutex, OwnedSemaphorePermit, broadcast},
+    time::Instant,
+};
+use tokio_stream::{Stream, StreamExt, wrappers::BroadcastStream};
+use tokio_util::{
+    io::{ReaderStream, StreamReader},
+    sync::CancellationToken,
 };
-use tokio_stream::StreamExt;
 use tracing::{debug, error, info};
-use twox_hash::XxHash3_128;
 
-use crate::{cache, config, disk};
+use crate::{
+    audit::{AuditAction, AuditLog},
+    cache, config, disk,
+    hash::{DeletionHasher, DigestHasher, UploadHasher},
+    mime,
+    ratelimit::{ConcurrencyLimiter, RateLimiter},
+    read,
+};
 
 /// Various forms of upload data that can be sent to the client
 pub enum UploadData {
     /// Send back the data from memory
     Cache(Bytes),
-    /// Stream the file from disk to the client
-    Disk(tokio::io::Take<File>),
+    /// Stream the file from disk to the client,
+    /// holding a disk read permit (if they're limited) until it's done
+    Disk(tokio::io::Take<File>, Option<OwnedSemaphorePermit>),
+    /// Send back several a..b ranges of the data from memory,
+    /// as a `multipart/byteranges` body
+    CacheParts(Bytes, Box<[(u64, u64)]>),
+    /// Stream several a..b ranges of the file from disk to the client,
+    /// as a `multipart/byteranges` body. Each is read by seeking to it in turn
+    DiskParts(File, Box<[(u64, u64)]>, Option<OwnedSemaphorePermit>),
 }
 
 /// Upload data and metadata needed to build a view response
 pub struct UploadResponse {
+    /// The upload's saved name, for logging
+    pub saved_name: String,
+    /// When the upload was created, if known
+    pub created: Option<SystemTime>,
+    /// Whether the upload was already in the cache
+    pub cache_hit: bool,
     pub full_len: u64,
+    /// The a..b range being sent.
+    /// This covers the whole upload if several ranges are
     pub range: (u64, u64),
+    /// Whether a range was asked for, even if it covers the whole upload
+    pub partial: bool,
+    /// Whether byte ranges of this response can be served.
+    /// This is false when the data isn't the upload as it's stored
+    pub ranges: bool,
+    /// How much to read at a time, if the data is streamed from disk
+    pub read_buffer: usize,
     pub data: UploadData,
 }
 
+/// Per-upload options given by the client
+#[derive(Clone, Copy, Default)]
+pub struct UploadOptions {
+    /// How long the upload should last, if it is temporary
+    pub lifetime: Option<Duration>,
+
+    /// How long a permanent upload should stay cached without being used,
+    /// instead of the default `cache.default_residency`
+    pub cache_lifetime: Option<Duration>,
+
+    /// Whether EXIF data should be left alone
+    pub keep_exif: bool,
+
+    /// SHA-256 digest the upload should be checked against (optional)
+    pub sha256: Option<[u8; 32]>,
+
+    /// How the client encoded the upload body
+    pub encoding: UploadEncoding,
+
+    /// IP address of the client uploading, for the audit log
+    pub client_ip: Option<IpAddr>,
+
+    /// Whether the upload is replacing an existing one,
+    /// so it has to be fully written before it takes its place
+    pub replace: bool,
+
+    /// Whether the upload should skip the cache and only be saved to disk,
+    /// like a backup that won't be read soon
+    pub no_cache: bool,
+
+    /// Whether the upload should be given out without a deletion URL
+    pub no_delete: bool,
+}
+
+/// How an upload body was encoded by the client (`Content-Encoding`)
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum UploadEncoding {
+    /// Sent as is
+    #[default]
+    Identity,
+
+    /// Compressed with gzip
+    Gzip,
+
+    /// Compressed with zlib (what HTTP calls `deflate`)
+    Deflate,
+}
+
+/// An upload turned out to be too large to keep while it was being saved
+/// (it grew once it was decoded, or there was no space in the cache for it)
+#[derive(Debug)]
+struct TooLarge;
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("upload is too large to keep")
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+/// EXIF data couldn't be removed from an image that needed it removed
+#[derive(Debug)]
+struct StripFailed;
+
+impl fmt::Display for StripFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("failed to strip exif data")
+    }
+}
+
+impl std::error::Error for StripFailed {}
+
+/// An upload was cancelled by an admin while it was being saved
+#[derive(Debug)]
+struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("upload was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// The i/o task stopped taking data, because writing to disk failed
+#[derive(Debug)]
+struct StorageFailed;
+
+impl fmt::Display for StorageFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("failed to write upload to disk")
+    }
+}
+
+impl std::error::Error for StorageFailed {}
+
 /// Non-error outcomes of an [`Engine::process`] call.
 /// Some are rejections.
 pub enum ProcessOutcome {
     /// The upload was successful.
-    /// We give the user their file's URL (and deletion URL if one was created)
+    /// We give the user their file's URL (and deletion URL if one was created),
+    /// along with how much was stored and what it will be served as
     Success {
         url: String,
         deletion_url: Option<String>,
+        stored_len: u64,
+        content_type: Option<&'static str>,
     },
 
     /// Occurs when an upload exceeds the chosen maximum file size.
     UploadTooLarge,
 
+    /// Occurs when an upload is below the chosen minimum file size.
+    UploadTooSmall,
+
+    /// Occurs when the maximum number of uploads are already saved to disk.
+    TooManyFiles,
+
+    /// Occurs when another upload is already being saved under the same name.
+    /// Only names the client chose can cause this, generated ones are retried
+    AlreadyInProgress,
+
+    /// Occurs when the upload doesn't match the digest the client gave.
+    DigestMismatch,
+
+    /// Occurs when EXIF data couldn't be removed from an image,
+    /// and `engine.require_exif_strip` is enabled.
+    ExifStripFailed,
+
     /// Occurs when a temporary upload is too big to fit in the cache.
     TemporaryUploadTooLarge,
 
     /// Occurs when the user-given lifetime is longer than we will allow
     TemporaryUploadLifetimeTooLong,
+
+    /// Occurs when an admin cancels the upload while it's being saved
+    Cancelled,
+
+    /// Occurs when the save path can't be written to
+    StorageUnavailable,
+
+    /// Occurs when the upload being replaced isn't saved to disk
+    NotFound,
 }
 
 /// Non-error outcomes of an [`Engine::get`] call.
 pub enum GetOutcome {
     /// Successfully read upload.
-    Success(UploadResponse),
+    Success(Box<UploadResponse>),
+
+    /// The upload was not found anywhere
+    NotFound,
+
+    /// The upload was temporary, and it expired recently
+    Gone,
+
+    /// A range was requested that exceeds an upload's bounds,
+    /// or that couldn't be understood.
+    /// Has the upload's length, if it was known
+    RangeNotSatisfiable(Option<u64>),
+}
+
+/// Non-error outcomes of an [`Engine::set_lifetime`] call.
+pub enum LifetimeOutcome {
+    /// The upload is now temporary, or permanent
+    Changed,
+
+    /// The upload's new expiry had already passed, so it was deleted
+    Deleted,
 
     /// The upload was not found anywhere
     NotFound,
 
-    /// A range was requested that exceeds an upload's bounds
-    RangeNotSatisfiable,
+    /// The upload is still being saved
+    InProgress,
+
+    /// The upload is too large to be temporary
+    TooLarge,
+
+    /// The lifetime is longer than `max_temp_lifetime`
+    LifetimeTooLong,
+
+    /// There's no room on disk to make the upload permanent
+    DiskFull,
+}
+
+/// Non-error outcomes of an [`Engine::load_cache`] call.
+pub enum LoadCacheOutcome {
+    /// The upload was loaded into the cache. This is its length
+    Loaded(u64),
+
+    /// The upload was not found on disk
+    NotFound,
+
+    /// The upload doesn't fit in the cache
+    TooLarge,
+}
+
+/// Non-error outcomes of an [`Engine::create_paste`] call.
+pub enum CreatePasteOutcome {
+    /// The paste was created.
+    /// We give the user its URL and the URL to append to it with
+    Success { url: String, append_url: String },
+
+    /// Occurs when the maximum number of uploads are already saved to disk.
+    TooManyFiles,
+}
+
+/// Non-error outcomes of an [`Engine::append`] call.
+pub enum AppendOutcome {
+    /// The data was appended.
+    Success,
+
+    /// The paste was not found on disk
+    NotFound,
+
+    /// The token given does not belong to the paste
+    InvalidToken,
+
+    /// Occurs when the paste would exceed the chosen maximum file size.
+    UploadTooLarge,
+}
+
+/// How far along an upload that is being written is
+pub struct UploadProgress {
+    /// When the upload started being saved
+    started: Instant,
+
+    /// How many bytes have been received so far
+    received: AtomicU64,
+
+    /// Cancelled to stop the upload
+    cancel: CancellationToken,
+}
+
+/// A claim on a saved name that is being written to.
+/// The name is released when this is dropped.
+struct InFlight<'a> {
+    map: &'a DashMap<String, Arc<UploadProgress>>,
+    saved_name: String,
+    progress: Arc<UploadProgress>,
+}
+
+impl<'a> InFlight<'a> {
+    /// Claim a saved name, or return [`None`] if it is already claimed
+    fn claim(map: &'a DashMap<String, Arc<UploadProgress>>, saved_name: &str) -> Option<Self> {
+        let Entry::Vacant(entry) = map.entry(saved_name.to_string()) else {
+            return None;
+        };
+
+        let progress = Arc::new(UploadProgress {
+            started: Instant::now(),
+            received: AtomicU64::new(0),
+            cancel: CancellationToken::new(),
+        });
+        entry.insert(progress.clone());
+
+        Some(Self {
+            map,
+            saved_name: saved_name.to_string(),
+            progress,
+        })
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.map.remove(&self.saved_name);
+    }
+}
+
+/// Live state of a paste that is being appended to or followed
+struct Paste {
+    /// Held while appending, so appends don't interleave
+    lock: tokio::sync::Mutex<()>,
+
+    /// Appended data (and the offset it was written at) is sent here for followers
+    tx: broadcast::Sender<(u64, Bytes)>,
 }
 
 /// Type alias to make using HMAC SHA256 easier
@@ -80,17 +372,111 @@ pub struct Engine {
     /// Cached count of uploaded files
     pub upl_count: AtomicUsize,
 
+    /// Whether the server has finished starting up
+    pub ready: AtomicBool,
+
     /// Engine configuration
     pub cfg: config::EngineConfig,
 
-    /// HMAC state initialised with the deletion secret (if present)
+    /// Paths the main routes are served on, for formatting URLs
+    pub routes: config::RoutesConfig,
+
+    /// HMAC state initialised with the primary deletion secret (if present),
+    /// used to sign new tokens
     pub deletion_hmac: Option<HmacSha256>,
 
+    /// HMAC states for every deletion secret, tried in turn when verifying tokens
+    deletion_hmacs: Vec<HmacSha256>,
+
+    /// Rate limiter for all deletion attempts (if configured)
+    pub deletion_attempts: Option<RateLimiter>,
+
+    /// Rate limiter for failed deletion attempts (if configured)
+    pub deletion_failures: Option<RateLimiter>,
+
+    /// Limits concurrent downloads per client (if configured)
+    pub downloads: Option<ConcurrencyLimiter>,
+
+    /// The most byte ranges a view request can ask for (if configured)
+    max_ranges: Option<usize>,
+
+    /// What to do with view requests that ask for more than that
+    excess_ranges: config::ExcessRanges,
+
+    /// Hosts of sites allowed to embed uploads (empty if any can)
+    pub allowed_referrers: Vec<String>,
+
+    /// Whether views without a referrer are allowed when it's checked
+    pub allow_no_referrer: bool,
+
+    /// Record of uploads and deletions (if configured)
+    pub audit_log: Option<AuditLog>,
+
     /// The in-memory cache that cached uploads are stored in
     cache: Arc<cache::Cache>,
 
     /// An interface to the on-disk upload store
     disk: Arc<disk::Disk>,
+
+    /// Pastes that have been created or appended to since startup.
+    /// Only these can be followed
+    pastes: DashMap<String, Arc<Paste>>,
+
+    /// Saved names of uploads that are currently being written
+    in_flight: DashMap<String, Arc<UploadProgress>>,
+
+    /// When the engine was started
+    started: Instant,
+
+    /// How many times removing EXIF data has failed, by file extension
+    strip_failures: DashMap<String, u64>,
+
+    /// How many uploads have been processed since startup, and their
+    /// total length, by (lowercase) file extension
+    ext_uploads: DashMap<String, (u64, u64)>,
+
+    /// Upper bounds of the upload size buckets, in order
+    size_buckets: Vec<u64>,
+
+    /// How many uploads were counted in each size bucket,
+    /// plus one more for those bigger than every bound
+    size_counts: Vec<AtomicU64>,
+
+    /// The last summary of files on disk we calculated, and when
+    disk_usage: Mutex<Option<(Instant, disk::DiskUsage)>>,
+}
+
+/// What's known about an upload, without its data
+pub struct UploadInfo {
+    /// Length of the upload (in bytes)
+    pub length: u64,
+
+    /// The content type it's served with, if it gets one
+    pub content_type: Option<&'static str>,
+
+    /// When it was created, if known
+    pub created: Option<SystemTime>,
+
+    /// When it expires, if it's temporary
+    pub expires: Option<SystemTime>,
+
+    /// Whether it's in the cache right now
+    pub cached: bool,
+}
+
+/// A summary of how the instance is being used
+pub struct Stats {
+    pub upl_count: usize,
+    pub temp_count: usize,
+    pub disk_usage: disk::DiskUsage,
+    pub cache_count: usize,
+    pub cache_length: usize,
+    pub strip_failures: BTreeMap<String, u64>,
+    pub ext_uploads: BTreeMap<String, (u64, u64)>,
+    /// Upper bound of each upload size bucket (`None` for the last),
+    /// and how many uploads were in it
+    pub upload_sizes: Vec<(Option<u64>, u64)>,
+    pub uptime: Duration,
 }
 
 /// Wipe out an upload from all storage.
@@ -100,19 +486,52 @@ async fn remove(cache: &cache::Cache, disk: &disk::Disk, saved_name: &str) -> ey
     info!(saved_name, "!! removing upload");
 
     cache.remove(saved_name);
-    disk.remove(saved_name)
-        .await
-        .wrap_err("failed to remove file from disk")?;
+
+    // temporary uploads are never saved to disk,
+    // so it's fine if there's nothing to remove
+    match disk.remove(saved_name).await {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        res => res.wrap_err("failed to remove file from disk")?,
+    }
 
     info!("!! successfully removed upload");
 
     Ok(())
 }
 
+/// Start the token needed to append to a paste, with a given secret's HMAC.
+/// Finalize it to get the token, or verify one against it
+fn paste_token_with(hmac: &HmacSha256, saved_name: &str) -> HmacSha256 {
+    let mut hmac = hmac.clone();
+
+    // the prefix keeps these from being mixed up with deletion hmacs
+    update_hmac(&mut hmac, &format!("paste:{saved_name}"), 0);
+
+    hmac
+}
+
+/// Wrap an upload body so it is decoded as it's read
+fn decode_stream(stream: BodyDataStream, encoding: UploadEncoding) -> BodyDataStream {
+    if encoding == UploadEncoding::Identity {
+        return stream;
+    }
+
+    let reader = StreamReader::new(stream.map(|res| res.map_err(std::io::Error::other)));
+    let body = match encoding {
+        UploadEncoding::Gzip => Body::from_stream(ReaderStream::new(GzipDecoder::new(reader))),
+        _ => Body::from_stream(ReaderStream::new(ZlibDecoder::new(reader))),
+    };
+
+    body.into_data_stream()
+}
+
 /// Try to parse a `Range` header into an easier format to work with
-fn resolve_range(range: Option<headers::Range>, full_len: u64) -> Option<(u64, u64)> {
+///
+/// Returns: the a..b range to send, and whether it was asked for
+/// (instead of being the whole upload by default)
+fn resolve_range(range: Option<headers::Range>, full_len: u64) -> Option<(u64, u64, bool)> {
     // Prepare default range
-    let default = Some((0, full_len));
+    let default = Some((0, full_len, false));
 
     // Take range, otherwise return
     let Some(range) = range else {
@@ -123,17 +542,32 @@ fn resolve_range(range: Option<headers::Range>, full_len: u64) -> Option<(u64, u
     let mut ranges = range.satisfiable_ranges(full_len);
 
     // Take first range
-    let Some(range) = ranges.next() else {
-        return default; // empty; use default
+    let Some(first) = ranges.next() else {
+        // Nothing could be parsed, or the only ranges given were
+        // suffixes longer than the upload. Those just mean the whole thing
+        if full_len > 0 && range.satisfiable_ranges(u64::MAX).next().is_some() {
+            return Some((0, full_len, true));
+        }
+        return None;
     };
 
-    // If there are multiple ranges, we will
-    // not process the request
+    // Several ranges are sent as separate parts,
+    // which `select_ranges` deals with
     if ranges.next().is_some() {
         return None;
     }
 
     // Convert into a..b range
+    let (start, end) = to_bounds(first, full_len)?;
+
+    // Return
+    Some((start, end, true))
+}
+
+/// Convert one range of a `Range` header into an a..b range
+///
+/// Returns: `None` if the range can't be satisfied
+fn to_bounds(range: (Bound<u64>, Bound<u64>), full_len: u64) -> Option<(u64, u64)> {
     let start = match range.start_bound() {
         Bound::Included(&x) => x,
         Bound::Excluded(&x) => x.checked_add(1)?,
@@ -145,9 +579,10 @@ fn resolve_range(range: Option<headers::Range>, full_len: u64) -> Option<(u64, u
         Bound::Unbounded => full_len,
     };
 
-    // We can't handle bounds
-    // out of order
-    if start > end {
+    // We can't handle bounds out of order, and empty ranges
+    // can't be satisfied (or written in a Content-Range header).
+    // This is what makes every range of an empty upload unsatisfiable
+    if start >= end {
         return None;
     }
 
@@ -157,10 +592,75 @@ fn resolve_range(range: Option<headers::Range>, full_len: u64) -> Option<(u64, u
         return None;
     }
 
-    // Return
     Some((start, end))
 }
 
+/// The parts of an upload a view should send
+#[derive(Debug, PartialEq, Eq)]
+enum Selection {
+    /// One a..b range, and whether it was asked for
+    Single(u64, u64, bool),
+    /// Several a..b ranges, in the order they were asked for
+    Multi(Vec<(u64, u64)>),
+}
+
+/// Work out which parts of an upload to send for a `Range` header.
+///
+/// If several ranges are asked for, the ones that can't be satisfied
+/// are left out, and the rest are sent as separate parts
+/// (or as a normal range response, if only one is left).
+///
+/// Returns: `None` if nothing asked for can be satisfied
+fn select_ranges(range: Option<headers::Range>, full_len: u64) -> Option<Selection> {
+    if let Some(r) = &range {
+        let wanted: Vec<_> = r.satisfiable_ranges(full_len).collect();
+        if wanted.len() > 1 {
+            let parts: Vec<_> = wanted
+                .into_iter()
+                .filter_map(|b| to_bounds(b, full_len))
+                .collect();
+
+            return match parts[..] {
+                [] => None,
+                [(start, end)] => Some(Selection::Single(start, end, true)),
+                _ => Some(Selection::Multi(parts)),
+            };
+        }
+    }
+
+    resolve_range(range, full_len)
+        .map(|(start, end, partial)| Selection::Single(start, end, partial))
+}
+
+/// Crockford's base32 alphabet, lowercased.
+/// It leaves out letters that look or sound like others (`i`, `l`, `o` and `u`)
+const UNAMBIGUOUS_ALPHABET: &[u8] = b"0123456789abcdefghjkmnpqrstvwxyz";
+
+/// Generate a random name of `len` characters from an alphabet
+fn random_name(alphabet: config::NameAlphabet, len: usize) -> String {
+    let mut rng = rand::rng();
+    match alphabet {
+        config::NameAlphabet::Alphanumeric => Alphanumeric.sample_string(&mut rng, len),
+        config::NameAlphabet::Unambiguous => (0..len)
+            .map(|_| {
+                char::from(
+                    *UNAMBIGUOUS_ALPHABET
+                        .choose(&mut rng)
+                        .expect("empty alphabet"),
+                )
+            })
+            .collect(),
+    }
+}
+
+/// Whether a range request asks for more than `max` ranges.
+///
+/// The upload's length isn't known yet when this is checked,
+/// so every range that could be satisfied for some length is counted.
+fn too_many_ranges(range: &headers::Range, max: usize) -> bool {
+    range.satisfiable_ranges(u64::MAX).take(max + 1).count() > max
+}
+
 /// Calculate HMAC of field values.
 pub fn update_hmac(hmac: &mut HmacSha256, saved_name: &str, hash: u128) {
     // mix deletion req fields into one buf
@@ -172,45 +672,217 @@ pub fn update_hmac(hmac: &mut HmacSha256, saved_name: &str, hash: u128) {
     hmac.update(&field_bytes);
 }
 
+/// How many appends can be waiting to be sent to a paste's followers
+/// before slow followers get cut off.
+const PASTE_FOLLOW_BACKLOG: usize = 256;
+
+/// How long a follow is kept open without anything being appended
+/// before it's ended. Followers can reconnect to keep going
+const PASTE_FOLLOW_IDLE: Duration = Duration::from_secs(300);
+
+/// How long the summary of files on disk is reused for
+/// before it is calculated again.
+const DISK_USAGE_TTL: Duration = Duration::from_secs(60);
+
 /// How many bytes of a file should be used for hash calculation.
+/// (from the start, and from the end if `deletion_hash_tail` is on)
 const SAMPLE_WANTED_BYTES: usize = 32768;
 
+/// The parts of an upload that its deletion hash is made from
+pub struct HashSample {
+    /// The first bytes of the upload
+    head: Bytes,
+
+    /// The last bytes of the upload, if they're being sampled too
+    tail: Option<Bytes>,
+}
+
 /// Format some info about an upload and hash it
 ///
 /// This should not change between versions!!
 /// That would break deletion urls
-fn calculate_hash(len: u64, data_sample: Bytes) -> u128 {
-    let mut buf = BytesMut::new();
-    buf.put_u64(len);
-    buf.put(data_sample);
+fn calculate_hash(len: u64, sample: &HashSample) -> u128 {
+    let mut hasher = DeletionHasher::default();
+    hasher.update(&len.to_be_bytes());
+    hasher.update(&sample.head);
+
+    // only when it's been opted into, so older urls still work without it
+    if let Some(ref tail) = sample.tail {
+        hasher.update(tail);
+    }
 
-    XxHash3_128::oneshot(&buf)
+    hasher.finish()
 }
 
 impl Engine {
     /// Creates a new instance of the engine
     pub fn new(
         cfg: config::EngineConfig,
+        http: &config::HttpConfig,
         cache: Arc<cache::Cache>,
         disk: disk::Disk,
     ) -> std::io::Result<Self> {
-        let deletion_hmac = cfg
+        let deletion_hmacs: Vec<_> = cfg
             .deletion_secret
-            .as_ref()
-            .map(|s| HmacSha256::new_from_slice(s.as_bytes()).unwrap());
+            .iter()
+            .map(|s| HmacSha256::new_from_slice(s.as_bytes()).unwrap())
+            .collect();
+        let deletion_hmac = deletion_hmacs.first().cloned();
+
+        let limit = &cfg.deletion_limit;
+        let deletion_attempts = limit.attempts.map(|n| RateLimiter::new(n, limit.window));
+        let deletion_failures = limit.failures.map(|n| RateLimiter::new(n, limit.window));
+
+        let downloads = http.max_downloads_per_ip.map(ConcurrencyLimiter::new);
+
+        let audit_log = cfg.audit_log.as_deref().map(AuditLog::open).transpose()?;
+
+        let mut size_buckets = cfg.upload_size_buckets.clone();
+        size_buckets.sort_unstable();
+        size_buckets.dedup();
+        let size_counts = (0..=size_buckets.len())
+            .map(|_| AtomicU64::new(0))
+            .collect();
 
         Ok(Self {
             // initialise our cached upload count. this doesn't include temp uploads!
             upl_count: AtomicUsize::new(disk.count()?),
+            ready: AtomicBool::new(false),
             deletion_hmac,
+            deletion_hmacs,
+            deletion_attempts,
+            deletion_failures,
+            downloads,
+            max_ranges: http.max_ranges_per_request,
+            excess_ranges: http.excess_ranges,
+            allowed_referrers: http.allowed_referrers.clone(),
+            allow_no_referrer: http.allow_no_referrer,
+            audit_log,
 
             cfg,
+            routes: http.routes.clone(),
 
             cache,
             disk: Arc::new(disk),
+            pastes: DashMap::new(),
+            in_flight: DashMap::new(),
+            started: Instant::now(),
+            strip_failures: DashMap::new(),
+            ext_uploads: DashMap::new(),
+            size_buckets,
+            size_counts,
+            disk_usage: Mutex::new(None),
         })
     }
 
+    /// Add an event to the audit log, if there is one
+    pub fn audit(
+        &self,
+        action: AuditAction,
+        saved_name: &str,
+        size: Option<u64>,
+        ip: Option<IpAddr>,
+    ) {
+        if let Some(ref audit_log) = self.audit_log {
+            audit_log.record(action, saved_name, size, ip);
+        }
+    }
+
+    /// Wait for uploads still being saved to disk,
+    /// and for the audit log to catch up
+    pub async fn wait_for_writes(&self) {
+        self.disk.wait_for_saves().await;
+        if let Some(ref audit_log) = self.audit_log {
+            audit_log.flush().await;
+        }
+    }
+
+    /// Format the deletion URL for an upload, given its hash
+    ///
+    /// Returns: `None` if deletion URLs aren't enabled
+    fn deletion_url(&self, saved_name: &str, hash: u128) -> Option<String> {
+        let mut hmac = self.deletion_hmac.clone()?;
+
+        let mut hash_bytes = BytesMut::new();
+        hash_bytes.put_u128(hash);
+        let hash_b64 = BASE64_URL_SAFE_NO_PAD.encode(&hash_bytes);
+
+        // take hmac
+        update_hmac(&mut hmac, saved_name, hash);
+        let out = hmac.finalize().into_bytes();
+        let out_b64 = BASE64_URL_SAFE_NO_PAD.encode(out);
+
+        // format deletion url
+        Some(format!(
+            "{}{}?name={saved_name}&hash={hash_b64}&hmac={out_b64}",
+            self.cfg.base_url, self.routes.delete
+        ))
+    }
+
+    /// Work out the deletion URL for an upload that's already saved,
+    /// the same way it was made when it was uploaded.
+    ///
+    /// Returns: `None` if deletion URLs aren't enabled, or the upload
+    /// doesn't exist (or is still being written)
+    pub async fn recover_deletion_url(&self, saved_name: &str) -> eyre::Result<Option<String>> {
+        if self.deletion_hmac.is_none() || self.in_flight.contains_key(saved_name) {
+            return Ok(None);
+        }
+
+        let Some(hash) = self.get_hash(saved_name).await? else {
+            return Ok(None);
+        };
+
+        Ok(self.deletion_url(saved_name, hash))
+    }
+
+    /// Wait a random amount of time, up to `engine.not_found_jitter`.
+    /// Used before "not found" responses, so they can't be told apart
+    /// from other ones by how quickly they come back
+    pub async fn not_found_jitter(&self) {
+        let Some(max) = self.cfg.not_found_jitter else {
+            return;
+        };
+
+        let delay = rand::rng().random_range(Duration::ZERO..=max);
+        tokio::time::sleep(delay).await;
+    }
+
+    /// Every upload that is being saved right now,
+    /// with how many bytes it has received and how long it has taken
+    pub fn in_flight_uploads(&self) -> Vec<(String, u64, Duration)> {
+        self.in_flight
+            .iter()
+            .map(|e| {
+                let progress = e.value();
+                (
+                    e.key().clone(),
+                    progress.received.load(Ordering::Relaxed),
+                    progress.started.elapsed(),
+                )
+            })
+            .collect()
+    }
+
+    /// Cancel an upload that is being saved. What it has saved so far is removed.
+    ///
+    /// Returns: `false` if the upload isn't being saved
+    pub fn cancel_upload(&self, saved_name: &str) -> bool {
+        let Some(progress) = self.in_flight.get(saved_name) else {
+            return false;
+        };
+
+        info!(saved_name, "!! cancelling upload");
+        progress.cancel.cancel();
+
+        true
+    }
+
+    /// Format the URL an upload can be viewed at
+    pub fn view_url(&self, saved_name: &str) -> String {
+        format!("{}{}/{saved_name}", self.cfg.base_url, self.routes.view)
+    }
+
     /// Fetch an upload.
     ///
     /// This will first try to read from cache, and then disk after.
@@ -218,70 +890,112 @@ impl Engine {
     /// sent back as a cache response instead of a disk response.
     ///
     /// If there is a range, it is applied at the very end.
+    /// Ranges of cached uploads never copy them; the data sent back
+    /// shares the cache entry's allocation.
     pub async fn get(
         &self,
         saved_name: &str,
-        range: Option<headers::Range>,
+        mut range: Option<headers::Range>,
     ) -> eyre::Result<GetOutcome> {
-        let data = if let Some(u) = self.cache.get(saved_name) {
-            u
+        // requests for too many ranges are dealt with before anything is read
+        if let Some(max) = self.max_ranges
+            && range.as_ref().is_some_and(|r| too_many_ranges(r, max))
+        {
+            match self.excess_ranges {
+                config::ExcessRanges::Reject => return Ok(GetOutcome::RangeNotSatisfiable(None)),
+                config::ExcessRanges::Full => range = None,
+            }
+        }
+
+        let (data, created, cache_hit) = if let Some(u) = self.cache.get(saved_name)
+            && self.is_cache_fresh(saved_name).await?
+        {
+            (u, self.cache.created(saved_name), true)
         } else {
             // now, check if we have it on disk
             let Some(mut f) = self.disk.open(saved_name).await? else {
-                // file didn't exist
+                // file didn't exist, but it might have once
+                if self.cache.expired_recently(saved_name) {
+                    return Ok(GetOutcome::Gone);
+                }
                 return Ok(GetOutcome::NotFound);
             };
 
-            // read length from disk
-            let full_len = self.disk.len(&f).await?;
+            // read length and modification time from disk
+            let metadata = self.disk.metadata(&f).await?;
+            let full_len = metadata.len();
+            let mtime = metadata.modified().ok();
 
             // if possible, recache and send a cache response
             // else, send a disk response
-            if self.cache.will_use(full_len) {
-                // read file from disk
-                let mut data = BytesMut::with_capacity(full_len.try_into()?);
-
-                // read file from disk and if it fails at any point, return 500
-                loop {
-                    match f.read_buf(&mut data).await {
-                        Ok(n) => {
-                            if n == 0 {
-                                break;
-                            }
-                        }
-                        Err(e) => Err(e)?,
-                    }
-                }
-
-                let data = data.freeze();
-
-                // re-insert it into cache
-                self.cache.add(saved_name, data.clone());
-
-                data
+            let ext = std::path::Path::new(saved_name)
+                .extension()
+                .and_then(|e| e.to_str());
+            if self.cache.will_use(ext, full_len)
+                || self.cache.should_promote(saved_name, ext, full_len)
+            {
+                let data = self.read_into_cache(saved_name, f, full_len, mtime).await?;
+
+                (data, mtime, false)
             } else {
-                let Some((start, end)) = resolve_range(range, full_len) else {
-                    return Ok(GetOutcome::RangeNotSatisfiable);
+                let Some(selection) = select_ranges(range, full_len) else {
+                    return Ok(GetOutcome::RangeNotSatisfiable(Some(full_len)));
                 };
 
-                // Set up file handle
-                f.seek(SeekFrom::Start(start)).await?;
-                let f = f.take(end - start);
+                // wait our turn, if too many uploads are being streamed from disk
+                let permit = self.disk.read_permit().await;
+
+                // Set up file handle.
+                // several ranges are seeked to as they're streamed
+                let (range, partial, data) = match selection {
+                    Selection::Single(start, end, partial) => {
+                        f.seek(SeekFrom::Start(start)).await?;
+                        let f = f.take(end - start);
+                        ((start, end), partial, UploadData::Disk(f, permit))
+                    }
+                    Selection::Multi(parts) => (
+                        (0, full_len),
+                        true,
+                        UploadData::DiskParts(f, parts.into(), permit),
+                    ),
+                };
 
                 // Return
                 let res = UploadResponse {
+                    saved_name: saved_name.to_string(),
+                    created: mtime,
+                    cache_hit: false,
                     full_len,
-                    range: (start, end),
-                    data: UploadData::Disk(f),
+                    range,
+                    partial,
+                    ranges: true,
+                    read_buffer: self.disk.read_buffer(),
+                    data,
                 };
-                return Ok(GetOutcome::Success(res));
+                return Ok(GetOutcome::Success(Box::new(res)));
             }
         };
 
         // Resolve a..b range
         let full_len = data.len() as u64;
-        let Some((start, end)) = resolve_range(range, full_len) else {
-            return Ok(GetOutcome::RangeNotSatisfiable);
+        let (start, end, partial) = match select_ranges(range, full_len) {
+            Some(Selection::Single(start, end, partial)) => (start, end, partial),
+            Some(Selection::Multi(parts)) => {
+                // each part is sliced out as it's sent
+                let res = UploadResponse {
+                    saved_name: saved_name.to_string(),
+                    created,
+                    cache_hit,
+                    full_len,
+                    range: (0, full_len),
+                    partial: true,
+                    ranges: true,
+                    read_buffer: self.disk.read_buffer(),
+                    data: UploadData::CacheParts(data, parts.into()),
+                };
+                return Ok(GetOutcome::Success(Box::new(res)));
+            }
+            None => return Ok(GetOutcome::RangeNotSatisfiable(Some(full_len))),
         };
 
         // Cut down to range
@@ -294,17 +1008,48 @@ impl Engine {
                 end.try_into().expect("end bound"),
             );
 
-            // Slice bytes
+            // Slice bytes. This only bumps a refcount, so
+            // serving lots of ranges of a big upload stays cheap
             data.slice(start..end)
         };
 
         // Build response
         let res = UploadResponse {
+            saved_name: saved_name.to_string(),
+            created,
+            cache_hit,
             full_len,
             range: (start, end),
+            partial,
+            ranges: true,
+            read_buffer: self.disk.read_buffer(),
             data: UploadData::Cache(data),
         };
-        Ok(GetOutcome::Success(res))
+        Ok(GetOutcome::Success(Box::new(res)))
+    }
+
+    /// Make sure a cached upload still matches its file on disk, if enabled.
+    /// Stale uploads are removed from the cache.
+    ///
+    /// Returns: `false` if the cached upload was stale
+    async fn is_cache_fresh(&self, saved_name: &str) -> eyre::Result<bool> {
+        if !self.cfg.verify_cache_mtime {
+            return Ok(true);
+        }
+
+        // temporary uploads aren't on disk, so there's nothing to compare against
+        let Some(mtime) = self.disk.mtime(saved_name).await? else {
+            return Ok(true);
+        };
+
+        if self.cache.sync_mtime(saved_name, mtime) {
+            return Ok(true);
+        }
+
+        info!(saved_name, "file changed on disk, dropping from cache");
+        self.cache.remove(saved_name);
+
+        Ok(false)
     }
 
     /// Check if we have an upload stored anywhere.
@@ -312,7 +1057,7 @@ impl Engine {
     /// This is only used to prevent `saved_name` collisions!!
     /// It is not used to deliver "not found" errors.
     pub async fn has(&self, saved_name: &str) -> bool {
-        if self.cache.has(saved_name) {
+        if self.in_flight.contains_key(saved_name) || self.cache.has(saved_name) {
             return true;
         }
 
@@ -325,18 +1070,69 @@ impl Engine {
         false
     }
 
+    /// Look up what's known about an upload, without reading its data
+    /// or counting as a view.
+    ///
+    /// Returns: [`None`] if it doesn't exist (or is still being uploaded)
+    pub async fn info(&self, saved_name: &str) -> eyre::Result<Option<UploadInfo>> {
+        if self.in_flight.contains_key(saved_name) {
+            return Ok(None);
+        }
+
+        let content_type = mime::media_type_for(saved_name);
+        let cached_len = self.cache.length_of(saved_name);
+
+        // uploads on disk are described by their file
+        if let Some(f) = self.disk.open(saved_name).await? {
+            let metadata = self.disk.metadata(&f).await?;
+            return Ok(Some(UploadInfo {
+                length: metadata.len(),
+                content_type,
+                created: metadata.modified().ok(),
+                expires: None,
+                cached: cached_len.is_some(),
+            }));
+        }
+
+        // temporary uploads only live in the cache
+        let Some(length) = cached_len else {
+            return Ok(None);
+        };
+        Ok(Some(UploadInfo {
+            length: length as u64,
+            content_type,
+            created: self.cache.created(saved_name),
+            expires: self.cache.expires_at(saved_name),
+            cached: true,
+        }))
+    }
+
+    /// How much of the start of an upload its deletion hash covers.
+    /// This is all of it, if it's under `engine.full_hash_below`
+    fn hash_head_len(&self, len: u64) -> u64 {
+        if self.cfg.full_hash_below.is_some_and(|t| len < t) {
+            len
+        } else {
+            len.min(SAMPLE_WANTED_BYTES as u64)
+        }
+    }
+
     /// Try to read a file and calculate a hash for it.
     pub async fn get_hash(&self, saved_name: &str) -> eyre::Result<Option<u128>> {
         // readout sample data and full len
-        let (data_sample, len) = if let Some(full_data) = self.cache.get(saved_name) {
+        let (sample, len) = if let Some(full_data) = self.cache.get(saved_name) {
             // we found it in cache! take as many bytes as we can
-            let taking = full_data.len().min(SAMPLE_WANTED_BYTES);
-            let data = full_data.slice(0..taking);
+            let taking = self.hash_head_len(full_data.len() as u64) as usize;
+            let head = full_data.slice(0..taking);
+            let tail = self.cfg.deletion_hash_tail.then(|| {
+                let taking = full_data.len().min(SAMPLE_WANTED_BYTES);
+                full_data.slice(full_data.len() - taking..)
+            });
             // get len
             let len = full_data.len() as u64;
 
             // return
-            (data, len)
+            (HashSample { head, tail }, len)
         } else {
             // not in cache, so try disk
             let Some(mut f) = self.disk.open(saved_name).await? else {
@@ -349,27 +1145,41 @@ impl Engine {
             f.rewind().await?;
 
             // only take wanted # of bytes for read
-            let mut f = f.take(SAMPLE_WANTED_BYTES as u64);
+            let taking = self.hash_head_len(len);
 
             // try to read
-            let mut data = Vec::with_capacity(SAMPLE_WANTED_BYTES);
-            f.read_to_end(&mut data).await?;
-            let data = Bytes::from(data);
+            let mut head = Vec::with_capacity(taking as usize);
+            (&mut f).take(taking).read_to_end(&mut head).await?;
+
+            // and the end too, if we're using it
+            let tail = if self.cfg.deletion_hash_tail {
+                let taking = len.min(SAMPLE_WANTED_BYTES as u64);
+                f.seek(SeekFrom::Start(len - taking)).await?;
+                let mut tail = Vec::with_capacity(SAMPLE_WANTED_BYTES);
+                f.take(taking).read_to_end(&mut tail).await?;
+                Some(Bytes::from(tail))
+            } else {
+                None
+            };
 
-            (data, len)
+            let head = Bytes::from(head);
+            (HashSample { head, tail }, len)
         };
 
         // calculate hash
-        Ok(Some(calculate_hash(len, data_sample)))
+        Ok(Some(calculate_hash(len, &sample)))
     }
 
     /// Generate a new saved name for an upload.
     ///
-    /// If it picks a name that already exists, it will try again.
-    pub async fn gen_saved_name(&self, ext: Option<String>) -> String {
-        loop {
-            // generate a 6-character alphanumeric string
-            let mut saved_name: String = Alphanumeric.sample_string(&mut rand::rng(), 6);
+    /// If it picks a name that already exists, it will try again with a
+    /// longer name, until it has tried `engine.max_name_attempts` times.
+    pub async fn gen_saved_name(&self, ext: Option<String>) -> eyre::Result<String> {
+        for attempt in 0..self.cfg.max_name_attempts {
+            // generate a random string, 6 characters to begin with.
+            // it gets longer every other collision to make another one less likely
+            let len = 6 + attempt as usize / 2;
+            let mut saved_name = random_name(self.cfg.name_alphabet, len);
 
             // if we have an extension, add it now
             if let Some(ref ext) = ext {
@@ -378,43 +1188,538 @@ impl Engine {
             }
 
             if !self.has(&saved_name).await {
-                break saved_name;
+                return Ok(saved_name);
             }
 
             // there was a name collision. loop and try again
             info!("name collision! saved_name= {}", saved_name);
         }
+
+        error!(
+            attempts = self.cfg.max_name_attempts,
+            "could not find an unused name for upload"
+        );
+        eyre::bail!("ran out of attempts to generate an unused saved name")
     }
 
     /// Wipe out an upload from all storage.
     ///
     /// (Intended for deletion URLs and failed uploads)
     pub async fn remove(&self, saved_name: &str) -> eyre::Result<()> {
+        // dropping the paste state also ends any follows
+        self.pastes.remove(saved_name);
+
         remove(&self.cache, &self.disk, saved_name).await
     }
 
-    /// Save a file to disk, and optionally cache.
+    /// Wipe out every upload on disk last modified within an age range.
+    /// Uploads that are still being written are left alone.
     ///
-    /// This also handles custom file lifetimes and EXIF data removal.
-    pub async fn save(
+    /// Returns: how many uploads were removed
+    /// (or would have been, if `dry_run` is set)
+    pub async fn remove_by_age(
         &self,
-        saved_name: &str,
-        provided_len: u64,
-        mut use_cache: bool,
-        mut stream: BodyDataStream,
-        lifetime: Option<Duration>,
-        keep_exif: bool,
-    ) -> eyre::Result<(Bytes, u64)> {
-        // if we're using cache, make some space to store the upload in
-        let mut data = if use_cache {
-            BytesMut::with_capacity(provided_len.try_into()?)
-        } else {
-            BytesMut::new()
+        older_than: Option<Duration>,
+        newer_than: Option<Duration>,
+        dry_run: bool,
+    ) -> eyre::Result<usize> {
+        let now = std::time::SystemTime::now();
+        let mut removed = 0;
+
+        for (saved_name, mtime) in self.disk.list().await? {
+            let age = now.duration_since(mtime).unwrap_or_default();
+            if older_than.is_some_and(|d| age <= d)
+                || newer_than.is_some_and(|d| age >= d)
+                || self.in_flight.contains_key(&saved_name)
+            {
+                continue;
+            }
+
+            if !dry_run {
+                self.remove(&saved_name).await?;
+                self.upl_count.fetch_sub(1, Ordering::Relaxed);
+                self.audit(AuditAction::Prune, &saved_name, None, None);
+            }
+            removed += 1;
+        }
+
+        info!(removed, dry_run, "!! removed uploads by age");
+
+        Ok(removed)
+    }
+
+    /// Get the live state of a paste, creating it if needed.
+    /// Only call this once the name is known to be a paste
+    fn paste(&self, saved_name: &str) -> Arc<Paste> {
+        self.pastes
+            .entry(saved_name.to_string())
+            .or_insert_with(|| {
+                Arc::new(Paste {
+                    lock: tokio::sync::Mutex::new(()),
+                    tx: broadcast::channel(PASTE_FOLLOW_BACKLOG).0,
+                })
+            })
+            .clone()
+    }
+
+    /// Check a deletion token against every deletion secret.
+    ///
+    /// Returns: whether any of them made it
+    pub fn verify_deletion_hmac(&self, saved_name: &str, hash: u128, provided: &[u8]) -> bool {
+        self.deletion_hmacs.iter().any(|hmac| {
+            let mut hmac = hmac.clone();
+            update_hmac(&mut hmac, saved_name, hash);
+            hmac.verify_slice(provided).is_ok()
+        })
+    }
+
+    /// Calculate the token needed to append to a paste.
+    ///
+    /// It is an HMAC taken with the primary deletion secret, so
+    /// this returns [`None`] if deletion URLs are disabled.
+    fn paste_token(&self, saved_name: &str) -> Option<Vec<u8>> {
+        self.deletion_hmac.as_ref().map(|hmac| {
+            paste_token_with(hmac, saved_name)
+                .finalize()
+                .into_bytes()
+                .to_vec()
+        })
+    }
+
+    /// Create a new, empty paste that can be appended to.
+    ///
+    /// Deletion URLs need to be enabled for this to work,
+    /// since the append token is made with the deletion secret.
+    pub async fn create_paste(&self) -> eyre::Result<CreatePasteOutcome> {
+        if !self.disk.will_fit(self.upl_count.load(Ordering::Relaxed)) {
+            return Ok(CreatePasteOutcome::TooManyFiles);
+        }
+
+        let saved_name = self.gen_saved_name(Some("txt".to_string())).await?;
+        self.disk
+            .create(&saved_name)
+            .await
+            .wrap_err("failed to create paste on disk")?;
+
+        let token = self
+            .paste_token(&saved_name)
+            .ok_or_else(|| eyre::eyre!("deletion secret is not set"))?;
+        let token_b64 = BASE64_URL_SAFE_NO_PAD.encode(token);
+
+        let url = self.view_url(&saved_name);
+        let append_url = format!("{}/paste/{saved_name}?token={token_b64}", self.cfg.base_url);
+
+        self.upl_count.fetch_add(1, Ordering::Relaxed);
+
+        // keep a record of it, so it can be followed
+        self.paste(&saved_name);
+
+        info!(saved_name, "created paste");
+
+        Ok(CreatePasteOutcome::Success { url, append_url })
+    }
+
+    /// Append data to the end of a paste, and send it to anyone following it.
+    pub async fn append(
+        &self,
+        saved_name: &str,
+        token: &str,
+        data: Bytes,
+    ) -> eyre::Result<AppendOutcome> {
+        // check the token first
+        let Ok(provided_token) = BASE64_URL_SAFE_NO_PAD.decode(token) else {
+            return Ok(AppendOutcome::InvalidToken);
+        };
+        // tokens made with an older secret are still good
+        let is_token_valid = self.deletion_hmacs.iter().any(|hmac| {
+            paste_token_with(hmac, saved_name)
+                .verify_slice(&provided_token)
+                .is_ok()
+        });
+        if !is_token_valid {
+            return Ok(AppendOutcome::InvalidToken);
+        }
+
+        let Some(f) = self.disk.open(saved_name).await? else {
+            return Ok(AppendOutcome::NotFound);
+        };
+
+        // a good token means it's a paste, even one from before a restart
+        let paste = self.paste(saved_name);
+        let _guard = paste.lock.lock().await;
+
+        // make sure the paste won't get too big
+        let len = self.disk.metadata(&f).await?.len();
+        if self
+            .cfg
+            .max_upload_len
+            .is_some_and(|l| len + data.len() as u64 > l)
+        {
+            return Ok(AppendOutcome::UploadTooLarge);
+        }
+
+        let Some(offset) = self.disk.append(saved_name, &data).await? else {
+            return Ok(AppendOutcome::NotFound);
+        };
+
+        // the cached copy (if there is one) is out of date now
+        self.cache.remove(saved_name);
+
+        // it's fine if nobody is following
+        let _ = paste.tx.send((offset, data));
+
+        Ok(AppendOutcome::Success)
+    }
+
+    /// Follow a paste: stream what it has now, then anything appended to it later.
+    /// The follow ends once nothing has been appended for a while.
+    ///
+    /// Only pastes created or appended to since startup can be followed.
+    ///
+    /// Returns: [`None`] if there is no such paste
+    pub async fn follow(
+        &self,
+        saved_name: &str,
+    ) -> eyre::Result<Option<impl Stream<Item = std::io::Result<Bytes>> + use<>>> {
+        let Some(paste) = self.pastes.get(saved_name).map(|p| p.clone()) else {
+            return Ok(None);
+        };
+
+        // subscribe before reading, so nothing appended in between is missed
+        let rx = paste.tx.subscribe();
+
+        let Some(f) = self.disk.open(saved_name).await? else {
+            return Ok(None);
+        };
+        let start = self.disk.metadata(&f).await?.len();
+
+        // stream what's there now like any other disk read.
+        // the read permit is released once it's been sent
+        let permit = self.disk.read_permit().await;
+        let existing =
+            ReaderStream::with_capacity(f.take(start), self.disk.read_buffer()).map(move |chunk| {
+                let _ = &permit;
+                chunk
+            });
+
+        // skip anything that we already read from disk
+        let appended = BroadcastStream::new(rx).filter_map(move |msg| match msg {
+            Ok((offset, data)) => {
+                let end = offset + data.len() as u64;
+                if end <= start {
+                    None
+                } else {
+                    let skip = start.saturating_sub(offset) as usize;
+                    Some(Ok(data.slice(skip..)))
+                }
+            }
+            // the follower fell too far behind, so end it and let them reconnect
+            Err(err) => Some(Err(std::io::Error::other(err))),
+        });
+        let appended = appended.timeout(PASTE_FOLLOW_IDLE).map_while(Result::ok);
+
+        Ok(Some(existing.chain(appended)))
+    }
+
+    /// The maximum length an upload can be to be cached.
+    /// Temporary uploads can't be longer than this.
+    pub fn max_cached_len(&self) -> usize {
+        self.cache.max_length()
+    }
+
+    /// Get a summary of how the instance is being used.
+    ///
+    /// The summary of files on disk (total length, oldest and newest)
+    /// is only recalculated every so often, since it means reading the whole store.
+    pub async fn stats(&self) -> eyre::Result<Stats> {
+        let cached = *self.disk_usage.lock().await;
+        let disk_usage = match cached {
+            Some((at, usage)) if at.elapsed() < DISK_USAGE_TTL => usage,
+            _ => {
+                let usage = self.disk.usage().await?;
+                *self.disk_usage.lock().await = Some((Instant::now(), usage));
+                usage
+            }
+        };
+
+        Ok(Stats {
+            upl_count: self.upl_count.load(Ordering::Relaxed),
+            temp_count: self.temp_count(),
+            disk_usage,
+            cache_count: self.cache.entry_count(),
+            cache_length: self.cache.total_length(),
+            strip_failures: self
+                .strip_failures
+                .iter()
+                .map(|e| (e.key().clone(), *e.value()))
+                .collect(),
+            ext_uploads: self
+                .ext_uploads
+                .iter()
+                .map(|e| (e.key().clone(), *e.value()))
+                .collect(),
+            upload_sizes: self
+                .size_buckets
+                .iter()
+                .map(|&le| Some(le))
+                .chain([None])
+                .zip(&self.size_counts)
+                .map(|(le, count)| (le, count.load(Ordering::Relaxed)))
+                .collect(),
+            uptime: self.started.elapsed(),
+        })
+    }
+
+    /// Count an upload in the first size bucket it fits in
+    fn count_size(&self, len: u64) {
+        let bucket = self.size_buckets.partition_point(|&le| le < len);
+        self.size_counts[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How many temporary uploads are currently stored
+    pub fn temp_count(&self) -> usize {
+        self.cache.temp_count()
+    }
+
+    /// Drop every upload from the cache.
+    ///
+    /// Returns: how many entries were removed, and their total length
+    pub fn clear_cache(&self) -> (usize, usize) {
+        info!("!! clearing cache");
+
+        self.cache.clear()
+    }
+
+    /// Read a whole upload from disk and insert it into the cache.
+    async fn read_into_cache(
+        &self,
+        saved_name: &str,
+        f: File,
+        full_len: u64,
+        mtime: Option<SystemTime>,
+    ) -> eyre::Result<Bytes> {
+        // read file from disk and if it fails at any point, return 500
+        let data = read::read_whole(f, full_len).await?;
+
+        // re-insert it into cache
+        self.cache.add(saved_name, data.clone());
+
+        if let Some(mtime) = mtime {
+            // the upload was created when it was saved, not now
+            self.cache.set_created(saved_name, mtime);
+
+            // remember which version of the file we cached
+            if self.cfg.verify_cache_mtime {
+                self.cache.sync_mtime(saved_name, mtime);
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Make an upload temporary, expiring `lifetime` from now,
+    /// or permanent if there's no lifetime. A lifetime of zero deletes it.
+    ///
+    /// Temporary uploads only live in the cache, so making an upload
+    /// temporary moves it there from disk, and making one permanent
+    /// saves it to disk. Either way, it keeps when it was created.
+    pub async fn set_lifetime(
+        &self,
+        saved_name: &str,
+        lifetime: Option<Duration>,
+    ) -> eyre::Result<LifetimeOutcome> {
+        if self.in_flight.contains_key(saved_name) {
+            return Ok(LifetimeOutcome::InProgress);
+        }
+
+        let temporary = self.cache.expires_at(saved_name).is_some();
+        let on_disk = !temporary && self.disk.mtime(saved_name).await?.is_some();
+        if !temporary && !on_disk {
+            return Ok(LifetimeOutcome::NotFound);
+        }
+
+        match lifetime {
+            // an expiry that has already passed
+            Some(Duration::ZERO) => {
+                self.remove(saved_name).await?;
+                if on_disk {
+                    self.upl_count.fetch_sub(1, Ordering::Relaxed);
+                }
+                return Ok(LifetimeOutcome::Deleted);
+            }
+            Some(lifetime) if lifetime > self.cfg.max_temp_lifetime => {
+                return Ok(LifetimeOutcome::LifetimeTooLong);
+            }
+            Some(lifetime) if temporary => {
+                self.cache.set_lifetime(saved_name, Some(lifetime));
+            }
+            Some(lifetime) => {
+                let Some(f) = self.disk.open(saved_name).await? else {
+                    return Ok(LifetimeOutcome::NotFound);
+                };
+                let metadata = self.disk.metadata(&f).await?;
+                let full_len = metadata.len();
+                if full_len > self.cache.max_length() as u64 {
+                    return Ok(LifetimeOutcome::TooLarge);
+                }
+
+                // move it into the cache. it's only taken off disk
+                // once it's there, so it can't be lost
+                let cached = if self.cache.has(saved_name) {
+                    self.cache.set_lifetime(saved_name, Some(lifetime))
+                } else {
+                    let data = read::read_whole(f, full_len).await?;
+                    let added = self.cache.add_temporary(saved_name, data, lifetime);
+                    if let Ok(mtime) = metadata.modified() {
+                        self.cache.set_created(saved_name, mtime);
+                    }
+                    added.is_some()
+                };
+                if !cached {
+                    return Ok(LifetimeOutcome::TooLarge);
+                }
+
+                self.disk
+                    .remove(saved_name)
+                    .await
+                    .wrap_err("failed to remove file from disk")?;
+                self.upl_count.fetch_sub(1, Ordering::Relaxed);
+            }
+            None if temporary => {
+                if !self.disk.will_fit(self.upl_count.load(Ordering::Relaxed)) {
+                    return Ok(LifetimeOutcome::DiskFull);
+                }
+                let Some(data) = self.cache.get(saved_name) else {
+                    return Ok(LifetimeOutcome::NotFound);
+                };
+
+                // write it under a hidden name first, so it's never
+                // on disk half-written under its own name
+                let part_name = format!(".permanent-{saved_name}");
+                let written = async {
+                    self.disk.create(&part_name).await?;
+                    self.disk.append(&part_name, &data).await?;
+                    self.disk.rename(&part_name, saved_name).await
+                };
+                if let Err(err) = written.await {
+                    let _ = self.disk.remove(&part_name).await;
+                    return Err(err).wrap_err("failed to save upload to disk");
+                }
+
+                // then let it be renewed like any other cached upload
+                self.cache.set_lifetime(saved_name, None);
+                self.upl_count.fetch_add(1, Ordering::Relaxed);
+            }
+            // it's already permanent
+            None => {}
+        }
+
+        info!(saved_name, ?lifetime, "changed upload lifetime");
+
+        Ok(LifetimeOutcome::Changed)
+    }
+
+    /// Load an upload from disk into the cache, even if it's over
+    /// the cache's `max_length`. If `pin` is set, it will stay cached
+    /// until it's evicted or the cache is cleared.
+    pub async fn load_cache(&self, saved_name: &str, pin: bool) -> eyre::Result<LoadCacheOutcome> {
+        let Some(f) = self.disk.open(saved_name).await? else {
+            return Ok(LoadCacheOutcome::NotFound);
+        };
+
+        let metadata = self.disk.metadata(&f).await?;
+        let full_len = metadata.len();
+        if !self.cache.could_fit(full_len) {
+            return Ok(LoadCacheOutcome::TooLarge);
+        }
+
+        info!(saved_name, pin, "!! loading upload into cache");
+
+        self.read_into_cache(saved_name, f, full_len, metadata.modified().ok())
+            .await?;
+
+        // pinned entries could be taking up the space it needed
+        if !self.cache.has(saved_name) {
+            return Ok(LoadCacheOutcome::TooLarge);
+        }
+        if pin {
+            self.cache.pin(saved_name);
+        }
+
+        Ok(LoadCacheOutcome::Loaded(full_len))
+    }
+
+    /// Check the save path can still be written to.
+    ///
+    /// Returns: whether it could be
+    pub async fn check_storage(&self) -> bool {
+        self.disk.check_writable().await
+    }
+
+    /// Drop a single upload from the cache, leaving it on disk.
+    ///
+    /// Returns: the length of the evicted upload, if it was cached
+    pub fn evict_cache(&self, saved_name: &str) -> Option<usize> {
+        info!(saved_name, "!! evicting upload from cache");
+
+        self.cache.remove(saved_name)
+    }
+
+    /// Save a file to disk, and optionally cache.
+    ///
+    /// This also handles custom file lifetimes and EXIF data removal.
+    ///
+    /// Returns: samples of the data for the deletion hash, the number of
+    /// bytes received, and their SHA-256 digest if the client gave one to check
+    pub async fn save(
+        &self,
+        saved_name: &str,
+        provided_len: u64,
+        mut use_cache: bool,
+        mut stream: BodyDataStream,
+        opts: &UploadOptions,
+        progress: &UploadProgress,
+    ) -> eyre::Result<(HashSample, u64, Option<[u8; 32]>)> {
+        let UploadOptions {
+            lifetime,
+            cache_lifetime,
+            keep_exif,
+            sha256: expected_sha256,
+            encoding,
+            ..
+        } = *opts;
+
+        // encoded uploads can grow once they're decoded,
+        // so their real length isn't known until they're done.
+        // make sure they don't grow past what the limits allow
+        let is_encoded = encoding != UploadEncoding::Identity;
+        let cache_limit = if is_encoded {
+            self.cache.max_length() as u64
+        } else {
+            provided_len
+        };
+        let max_len = if is_encoded {
+            let max = self.cfg.max_upload_len.unwrap_or(u64::MAX);
+            // temporary uploads only live in the cache
+            Some(if lifetime.is_some() {
+                max.min(cache_limit)
+            } else {
+                max
+            })
+        } else {
+            None
+        };
+
+        // if we're using cache, make some space to store the upload in
+        let mut data = if use_cache {
+            BytesMut::with_capacity(provided_len.try_into()?)
+        } else {
+            BytesMut::new()
         };
 
         // don't begin a disk save if we're using temporary lifetimes
-        let tx = if lifetime.is_none() {
-            Some(self.disk.start_save(saved_name, {
+        let (tx, written) = if lifetime.is_none() {
+            Some(self.disk.start_save(saved_name, false, {
                 let cache = self.cache.clone();
                 let disk = self.disk.clone();
                 let saved_name = saved_name.to_string();
@@ -429,12 +1734,13 @@ impl Engine {
             }))
         } else {
             None
-        };
+        }
+        .unzip();
 
         // whether or not we are going to coalesce the data
         // in order to strip the exif data at the end,
         // instead of just sending it off to the i/o task
-        let coalesce_and_strip = use_cache
+        let mut coalesce_and_strip = use_cache
             && matches!(
                 std::path::Path::new(saved_name)
                     .extension()
@@ -446,47 +1752,94 @@ impl Engine {
 
         // buffer of sampled data for the deletion hash
         let mut hash_sample = BytesMut::with_capacity(SAMPLE_WANTED_BYTES);
+        // small enough uploads are hashed whole, and we don't know
+        // the real length until the end, so keep enough for that
+        let head_wanted = self.cfg.full_hash_below.map_or(SAMPLE_WANTED_BYTES, |t| {
+            (t as usize).max(SAMPLE_WANTED_BYTES)
+        });
+        // the last bytes seen so far, if the end is sampled too
+        let mut tail_sample = self
+            .cfg
+            .deletion_hash_tail
+            .then(|| BytesMut::with_capacity(SAMPLE_WANTED_BYTES));
         // actual number of bytes processed
         let mut observed_len = 0;
+        // digest of the data as we received it
+        let mut hasher = expected_sha256.map(|_| DigestHasher::default());
 
         // read and save upload
-        while let Some(chunk) = stream.next().await {
+        loop {
+            // stop waiting for more if an admin cancelled the upload
+            let chunk = tokio::select! {
+                chunk = stream.next() => chunk,
+                () = progress.cancel.cancelled() => return Err(Cancelled.into()),
+            };
+            let Some(chunk) = chunk else {
+                break;
+            };
+
             // if we error on a chunk, fail out
             let chunk = chunk?;
 
+            // record new len
+            observed_len += chunk.len() as u64;
+            progress.received.store(observed_len, Ordering::Relaxed);
+            if max_len.is_some_and(|l| observed_len > l) {
+                return Err(TooLarge.into());
+            }
+
+            if use_cache && (data.len() + chunk.len()) as u64 > cache_limit {
+                info!(
+                    "the upload is bigger than expected! (or than the content-length provided by the client) caching will be cancelled for this upload."
+                );
+
+                // anything held back to strip exif data from has to go to disk now
+                if coalesce_and_strip {
+                    coalesce_and_strip = false;
+
+                    if let Some(ref tx) = tx {
+                        tx.send(data.split().freeze())
+                            .await
+                            .map_err(|_| StorageFailed)?;
+                    }
+                }
+
+                // if we receive too much data, drop the buffer and stop using cache (it is still okay to use disk, probably)
+                data = BytesMut::new();
+                use_cache = false;
+            }
+
             // if we have an i/o task, send it off
             // also cloning this is okay because it's a Bytes
             if !coalesce_and_strip && let Some(ref tx) = tx {
                 debug!("sending chunk to i/o task");
-                tx.send(chunk.clone())
-                    .await
-                    .wrap_err("failed to send chunk to i/o task!")?;
+                tx.send(chunk.clone()).await.map_err(|_| StorageFailed)?;
             }
 
             // add to sample if we need to
-            let wanted = SAMPLE_WANTED_BYTES - hash_sample.len();
+            let wanted = head_wanted - hash_sample.len();
             if wanted != 0 {
                 // take as many bytes as we can ...
                 let taking = chunk.len().min(wanted);
                 hash_sample.extend_from_slice(&chunk[0..taking]);
             }
-            // record new len
-            observed_len += chunk.len() as u64;
 
-            if use_cache {
-                debug!("receiving data into buffer");
+            // keep the end of what we've seen, in case it's the end of the file
+            if let Some(ref mut tail_sample) = tail_sample {
+                let chunk_tail = &chunk[chunk.len().saturating_sub(SAMPLE_WANTED_BYTES)..];
+                let keeping = SAMPLE_WANTED_BYTES - chunk_tail.len();
+                tail_sample.advance(tail_sample.len().saturating_sub(keeping));
+                tail_sample.extend_from_slice(chunk_tail);
+            }
 
-                if data.len() + chunk.len() > data.capacity() {
-                    info!(
-                        "the amount of data sent exceeds the content-length provided by the client! caching will be cancelled for this upload."
-                    );
+            // only hash the data if there's something to check it against
+            if let Some(ref mut hasher) = hasher {
+                hasher.update(&chunk);
+            }
 
-                    // if we receive too much data, drop the buffer and stop using cache (it is still okay to use disk, probably)
-                    data = BytesMut::new();
-                    use_cache = false;
-                } else {
-                    data.put(chunk);
-                }
+            if use_cache {
+                debug!("receiving data into buffer");
+                data.put(chunk);
             }
         }
 
@@ -496,26 +1849,38 @@ impl Engine {
         // strip the exif data and send it off now
         let data = if coalesce_and_strip {
             // strip the exif if we can
-            // if we can't, then oh well
-            let data = if let Ok(Some(data)) = DynImage::from_bytes(data.clone()).map(|o| {
-                o.map(|mut img| {
+            // if we can't, then oh well (unless that isn't allowed)
+            let data = match DynImage::from_bytes(data.clone()) {
+                Ok(Some(mut img)) => {
                     img.set_exif(None);
+                    info!("stripped exif data");
                     img.encoder().bytes()
-                })
-            }) {
-                info!("stripped exif data");
-                data
-            } else {
-                info!("failed to strip exif data");
-                data
+                }
+                res => {
+                    let format = std::path::Path::new(saved_name)
+                        .extension()
+                        .and_then(|e| e.to_str())
+                        .unwrap_or_default()
+                        .to_ascii_lowercase();
+                    match res {
+                        Err(err) => debug!(%format, %err, "could not parse image"),
+                        _ => debug!(%format, "image format was not recognised"),
+                    }
+                    info!("failed to strip exif data");
+
+                    *self.strip_failures.entry(format).or_default() += 1;
+                    if self.cfg.require_exif_strip {
+                        return Err(StripFailed.into());
+                    }
+
+                    data
+                }
             };
 
             // send what we did over to the i/o task, all in one chunk
             if let Some(ref tx) = tx {
                 debug!("sending filled buffer to i/o task");
-                tx.send(data.clone())
-                    .await
-                    .wrap_err("failed to send coalesced buffer to i/o task!")?;
+                tx.send(data.clone()).await.map_err(|_| StorageFailed)?;
             }
 
             data
@@ -526,100 +1891,1055 @@ impl Engine {
         };
 
         // insert upload into cache if we're using it
-        if use_cache {
+        let cached = use_cache && {
             info!("caching upload!");
-            match lifetime {
-                Some(lt) => self.cache.add_with_lifetime(saved_name, data, lt, false),
-                None => self.cache.add(saved_name, data),
-            };
+            match (lifetime, cache_lifetime) {
+                (Some(lt), _) => self.cache.add_temporary(saved_name, data, lt),
+                (None, Some(residency)) => self
+                    .cache
+                    .add_with_lifetime(saved_name, data, residency, true),
+                (None, None) => self.cache.add(saved_name, data),
+            }
+            .is_some()
+        };
+
+        // temporary uploads only live in the cache,
+        // so if it couldn't take this one it can't be kept
+        if lifetime.is_some() && !cached {
+            return Err(TooLarge.into());
+        }
+
+        // a replacement can't take the old upload's place until it's all on disk
+        if opts.replace
+            && let Some(written) = written
+        {
+            drop(tx);
+            written.await.map_err(|_| StorageFailed)?;
         }
 
         // return w/ info for hash calculation
-        Ok((hash_sample.freeze(), observed_len))
+        let sha256 = hasher.map(UploadHasher::finish);
+        hash_sample.truncate(self.hash_head_len(observed_len) as usize);
+        let sample = HashSample {
+            head: hash_sample.freeze(),
+            tail: tail_sample.map(BytesMut::freeze),
+        };
+        Ok((sample, observed_len, sha256))
     }
 
-    pub async fn process(
-        &self,
-        ext: Option<String>,
-        provided_len: u64,
-        stream: BodyDataStream,
-        lifetime: Option<Duration>,
-        keep_exif: bool,
-    ) -> eyre::Result<ProcessOutcome> {
+    /// Run the checks an upload has to pass before any of it is received.
+    ///
+    /// Returns: why the upload would be rejected, if it would be
+    pub fn precheck(&self, provided_len: u64, opts: &UploadOptions) -> Option<ProcessOutcome> {
+        let lifetime = opts.lifetime;
+
         // if the upload size is greater than our max file size, deny it now
         if self.cfg.max_upload_len.is_some_and(|l| provided_len > l) {
-            return Ok(ProcessOutcome::UploadTooLarge);
+            return Some(ProcessOutcome::UploadTooLarge);
         }
 
-        // if the upload size is smaller than the specified maximum, we use the cache!
-        let use_cache = self.cache.will_use(provided_len);
+        // same if it's smaller than our min file size.
+        // encoded uploads are only checked once they've been decoded
+        if opts.encoding == UploadEncoding::Identity
+            && self.cfg.min_upload_len.is_some_and(|l| provided_len < l)
+        {
+            return Some(ProcessOutcome::UploadTooSmall);
+        }
 
         // if a temp file is too big for cache, reject it now
-        if lifetime.is_some() && !use_cache {
-            return Ok(ProcessOutcome::TemporaryUploadTooLarge);
+        if lifetime.is_some() && provided_len > self.cache.max_length() as u64 {
+            return Some(ProcessOutcome::TemporaryUploadTooLarge);
         }
 
         // if a temp file's lifetime is too long, reject it now
         if lifetime.is_some_and(|lt| lt > self.cfg.max_temp_lifetime) {
-            return Ok(ProcessOutcome::TemporaryUploadLifetimeTooLong);
+            return Some(ProcessOutcome::TemporaryUploadLifetimeTooLong);
         }
 
-        // generate the file name
-        let saved_name = self.gen_saved_name(ext).await;
+        // if we're saving to disk and it's already full, reject it now.
+        // replacing an upload doesn't add one
+        if lifetime.is_none()
+            && !opts.replace
+            && !self.disk.will_fit(self.upl_count.load(Ordering::Relaxed))
+        {
+            return Some(ProcessOutcome::TooManyFiles);
+        }
+
+        None
+    }
+
+    /// Save an upload with [`Engine::save`], then make sure it's one we can keep.
+    /// If it isn't, whatever was saved is removed again.
+    ///
+    /// Returns: samples of the data for the deletion hash and its length,
+    /// or why the upload was rejected
+    async fn save_checked(
+        &self,
+        saved_name: &str,
+        provided_len: u64,
+        use_cache: bool,
+        stream: BodyDataStream,
+        opts: &UploadOptions,
+        progress: &UploadProgress,
+    ) -> eyre::Result<Result<(HashSample, u64), ProcessOutcome>> {
+        let lifetime = opts.lifetime;
 
         // save it
+        let stream = decode_stream(stream, opts.encoding);
         let save_result = self
-            .save(
-                &saved_name,
-                provided_len,
-                use_cache,
-                stream,
-                lifetime,
-                keep_exif,
-            )
+            .save(saved_name, provided_len, use_cache, stream, opts, progress)
             .await;
 
         // handle result
-        let (hash_sample, len) = match save_result {
+        let (hash_sample, len, sha256) = match save_result {
             // Okay so just extract metadata
             Ok(m) => m,
+            // EXIF data had to be removed but couldn't be
+            Err(err) if err.is::<StripFailed>() => {
+                info!(saved_name, "could not strip exif data, removing");
+
+                self.remove(saved_name).await?;
+                return Ok(Err(ProcessOutcome::ExifStripFailed));
+            }
+            // The upload turned out to be too big to keep
+            Err(err) if err.is::<TooLarge>() => {
+                info!(saved_name, "upload too large to keep, removing");
+
+                self.remove(saved_name).await?;
+                return Ok(Err(if lifetime.is_some() {
+                    ProcessOutcome::TemporaryUploadTooLarge
+                } else {
+                    ProcessOutcome::UploadTooLarge
+                }));
+            }
+            // An admin stopped it
+            Err(err) if err.is::<Cancelled>() => {
+                info!(saved_name, "upload was cancelled, removing");
+
+                self.remove(saved_name).await?;
+                return Ok(Err(ProcessOutcome::Cancelled));
+            }
+            // The disk stopped taking it
+            Err(err) if err.is::<StorageFailed>() => {
+                error!(saved_name, "upload could not be written to disk, removing");
+
+                // the file may never have been created
+                if let Err(err) = self.remove(saved_name).await {
+                    debug!(saved_name, %err, "failed to remove upload that failed to save");
+                }
+                return Ok(Err(ProcessOutcome::StorageUnavailable));
+            }
             // If anything fails, delete the upload and return the error
             Err(err) => {
                 error!(?err, "failed processing upload!");
 
-                self.remove(&saved_name).await?;
+                self.remove(saved_name).await?;
                 return Err(err);
             }
         };
 
-        // if deletion urls are enabled, create one
-        let deletion_url = self.deletion_hmac.clone().map(|mut hmac| {
-            // calculate hash of file metadata
-            let hash = calculate_hash(len, hash_sample);
-            let mut hash_bytes = BytesMut::new();
-            hash_bytes.put_u128(hash);
-            let hash_b64 = BASE64_URL_SAFE_NO_PAD.encode(&hash_bytes);
+        // the client could have sent less data than it said it would,
+        // so check the min file size again now that we know the real length
+        if self.cfg.min_upload_len.is_some_and(|l| len < l) {
+            info!(saved_name, len, "upload too small, removing");
 
-            // take hmac
-            update_hmac(&mut hmac, &saved_name, hash);
-            let out = hmac.finalize().into_bytes();
-            let out_b64 = BASE64_URL_SAFE_NO_PAD.encode(out);
+            self.remove(saved_name).await?;
+            return Ok(Err(ProcessOutcome::UploadTooSmall));
+        }
 
-            // format deletion url
-            format!(
-                "{}/del?name={saved_name}&hash={hash_b64}&hmac={out_b64}",
-                self.cfg.base_url
-            )
-        });
+        // if the client told us what the upload should hash to, make sure it does
+        if let Some(expected) = opts.sha256
+            && sha256 != Some(expected)
+        {
+            info!(saved_name, "upload did not match digest, removing");
+
+            self.remove(saved_name).await?;
+            return Ok(Err(ProcessOutcome::DigestMismatch));
+        }
+
+        Ok(Ok((hash_sample, len)))
+    }
+
+    pub async fn process(
+        &self,
+        ext: Option<String>,
+        provided_len: u64,
+        stream: BodyDataStream,
+        opts: UploadOptions,
+    ) -> eyre::Result<ProcessOutcome> {
+        if let Some(rejection) = self.admit(provided_len, &opts).await {
+            return Ok(rejection);
+        }
+
+        // if the upload size is smaller than the specified maximum, we use the cache!
+        let use_cache = self.should_cache(ext.as_deref(), provided_len, &opts);
+
+        // generate the file name, and claim it while we write to it.
+        // another upload could have picked the same name since we checked,
+        // but the client didn't choose it, so just pick another
+        let mut claimed = None;
+        for _ in 0..self.cfg.max_name_attempts {
+            let saved_name = self.gen_saved_name(ext.clone()).await?;
+            if let Some(in_flight) = InFlight::claim(&self.in_flight, &saved_name) {
+                claimed = Some((saved_name, in_flight));
+                break;
+            }
+
+            info!(
+                "name was claimed by another upload! saved_name= {}",
+                saved_name
+            );
+        }
+        let Some((saved_name, in_flight)) = claimed else {
+            error!(
+                attempts = self.cfg.max_name_attempts,
+                "could not claim a name for upload"
+            );
+            eyre::bail!("ran out of attempts to claim a saved name");
+        };
+
+        self.store(
+            &saved_name,
+            provided_len,
+            use_cache,
+            stream,
+            opts,
+            &in_flight.progress,
+        )
+        .await
+    }
+
+    /// Save a new upload under exactly the name given, instead of a generated one.
+    ///
+    /// This never overwrites anything; if the name is taken, it's rejected.
+    /// The caller has to make sure the name is a sensible one!!
+    pub async fn process_named(
+        &self,
+        saved_name: &str,
+        provided_len: u64,
+        stream: BodyDataStream,
+        opts: UploadOptions,
+    ) -> eyre::Result<ProcessOutcome> {
+        if let Some(rejection) = self.admit(provided_len, &opts).await {
+            return Ok(rejection);
+        }
+
+        let ext = std::path::Path::new(saved_name)
+            .extension()
+            .and_then(|e| e.to_str());
+        let use_cache = self.should_cache(ext, provided_len, &opts);
+
+        let Some(in_flight) = InFlight::claim(&self.in_flight, saved_name) else {
+            return Ok(ProcessOutcome::AlreadyInProgress);
+        };
+
+        // something could have been saved there since the caller checked
+        if self.cache.has(saved_name) || self.disk.mtime(saved_name).await?.is_some() {
+            return Ok(ProcessOutcome::AlreadyInProgress);
+        }
+
+        self.store(
+            saved_name,
+            provided_len,
+            use_cache,
+            stream,
+            opts,
+            &in_flight.progress,
+        )
+        .await
+    }
+
+    /// Whether a new upload should be cached as well as saved to disk.
+    /// Temporary uploads always are, since they have nowhere else to live
+    fn should_cache(&self, ext: Option<&str>, provided_len: u64, opts: &UploadOptions) -> bool {
+        opts.lifetime.is_some() || (!opts.no_cache && self.cache.will_use(ext, provided_len))
+    }
+
+    /// Check whether an upload can be taken at all, before reading any of it
+    async fn admit(&self, provided_len: u64, opts: &UploadOptions) -> Option<ProcessOutcome> {
+        if let Some(rejection) = self.precheck(provided_len, opts) {
+            return Some(rejection);
+        }
+
+        // if writing to disk failed last time, see if it's come back before trying again
+        if opts.lifetime.is_none() && !self.disk.is_writable() && !self.disk.check_writable().await
+        {
+            return Some(ProcessOutcome::StorageUnavailable);
+        }
+
+        None
+    }
+
+    /// Save a new upload under a name that's already been claimed,
+    /// and hand out its URLs
+    async fn store(
+        &self,
+        saved_name: &str,
+        provided_len: u64,
+        use_cache: bool,
+        stream: BodyDataStream,
+        opts: UploadOptions,
+        progress: &UploadProgress,
+    ) -> eyre::Result<ProcessOutcome> {
+        // save it, and make sure it's fine to keep
+        let (hash_sample, len) = match self
+            .save_checked(saved_name, provided_len, use_cache, stream, &opts, progress)
+            .await?
+        {
+            Ok(saved) => saved,
+            Err(rejection) => return Ok(rejection),
+        };
+
+        // if deletion urls are enabled (and wanted), create one
+        let deletion_url = (!opts.no_delete)
+            .then(|| self.deletion_url(saved_name, calculate_hash(len, &hash_sample)))
+            .flatten();
 
         // format and send back the url
-        let url = format!("{}/p/{saved_name}", self.cfg.base_url);
+        let url = self.view_url(saved_name);
 
         // if all goes well, increment the cached upload counter
         self.upl_count.fetch_add(1, Ordering::Relaxed);
 
+        // uploads without an extension are counted under an empty one
+        let ext = std::path::Path::new(saved_name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let mut counts = self.ext_uploads.entry(ext).or_default();
+        counts.0 += 1;
+        counts.1 += len;
+        drop(counts);
+        self.count_size(len);
+
+        self.audit(AuditAction::Upload, saved_name, Some(len), opts.client_ip);
+
         info!("finished processing upload!");
 
-        Ok(ProcessOutcome::Success { url, deletion_url })
+        Ok(ProcessOutcome::Success {
+            url,
+            deletion_url,
+            stored_len: len,
+            content_type: mime::media_type_for(saved_name),
+        })
+    }
+
+    /// Replace an existing upload with a new one, keeping its name (and URL).
+    ///
+    /// The new upload is written next to the old one, and only moved over
+    /// it once it's complete, so nobody sees it half-written.
+    /// The caller has to make sure the client is allowed to do this!!
+    pub async fn replace(
+        &self,
+        saved_name: &str,
+        provided_len: u64,
+        stream: BodyDataStream,
+        mut opts: UploadOptions,
+    ) -> eyre::Result<ProcessOutcome> {
+        // replacements are permanent, like what they replace
+        opts.lifetime = None;
+        opts.replace = true;
+
+        if let Some(rejection) = self.admit(provided_len, &opts).await {
+            return Ok(rejection);
+        }
+
+        let ext = std::path::Path::new(saved_name)
+            .extension()
+            .and_then(|e| e.to_str());
+        let use_cache = self.should_cache(ext, provided_len, &opts);
+
+        // only one upload can be writing to a name at once
+        let Some(in_flight) = InFlight::claim(&self.in_flight, saved_name) else {
+            return Ok(ProcessOutcome::AlreadyInProgress);
+        };
+
+        // temporary uploads aren't on disk, and can't be replaced
+        if self.disk.mtime(saved_name).await?.is_none() {
+            return Ok(ProcessOutcome::NotFound);
+        }
+
+        // hidden, so it's never counted as an upload, but keeps the extension
+        let part_name = format!(".replacing-{saved_name}");
+        let (hash_sample, len) = match self
+            .save_checked(
+                &part_name,
+                provided_len,
+                use_cache,
+                stream,
+                &opts,
+                &in_flight.progress,
+            )
+            .await?
+        {
+            Ok(saved) => saved,
+            Err(rejection) => return Ok(rejection),
+        };
+
+        // swap it in on disk, then in the cache
+        if let Err(err) = self.disk.rename(&part_name, saved_name).await {
+            self.remove(&part_name).await?;
+            return Err(err).wrap_err("failed to move replacement into place");
+        }
+        self.cache.rename(&part_name, saved_name);
+
+        let deletion_url = (!opts.no_delete)
+            .then(|| self.deletion_url(saved_name, calculate_hash(len, &hash_sample)))
+            .flatten();
+        let url = self.view_url(saved_name);
+
+        self.audit(AuditAction::Upload, saved_name, Some(len), opts.client_ip);
+
+        info!(saved_name, "finished replacing upload!");
+
+        Ok(ProcessOutcome::Success {
+            url,
+            deletion_url,
+            stored_len: len,
+            content_type: mime::media_type_for(saved_name),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        path::PathBuf,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use async_compression::tokio::bufread::GzipEncoder;
+    use axum::{body::Body, response::IntoResponse};
+    use base64::{Engine as _, prelude::BASE64_URL_SAFE_NO_PAD};
+    use bytes::Bytes;
+    use headers::{Header, Range};
+    use http::HeaderValue;
+    use tokio::io::AsyncReadExt;
+    use tokio_stream::StreamExt;
+
+    use super::{
+        AppendOutcome, CreatePasteOutcome, Engine, GetOutcome, HashSample, LifetimeOutcome,
+        ProcessOutcome, Selection, UNAMBIGUOUS_ALPHABET, UploadData, UploadEncoding, UploadOptions,
+        calculate_hash, decode_stream, random_name, resolve_range, select_ranges, too_many_ranges,
+    };
+    use crate::{
+        cache::Cache,
+        config::{Config, NameAlphabet},
+        disk::Disk,
+    };
+
+    const FULL_LEN: u64 = 1000;
+
+    fn resolve(range: &str) -> Option<(u64, u64, bool)> {
+        let value = HeaderValue::from_str(range).unwrap();
+        let range = Range::decode(&mut std::iter::once(&value)).unwrap();
+
+        resolve_range(Some(range), FULL_LEN)
+    }
+
+    /// Make sure the ranges a browser sends
+    /// while seeking through media resolve properly
+    #[test]
+    fn resolve_seeking_ranges() {
+        // no range
+        assert_eq!(resolve_range(None, FULL_LEN), Some((0, FULL_LEN, false)));
+
+        // first request, then seeking forwards and backwards
+        assert_eq!(resolve("bytes=0-"), Some((0, FULL_LEN, true)));
+        assert_eq!(resolve("bytes=600-"), Some((600, FULL_LEN, true)));
+        assert_eq!(resolve("bytes=200-"), Some((200, FULL_LEN, true)));
+
+        // bounded, and from the end (looking for metadata)
+        assert_eq!(resolve("bytes=100-199"), Some((100, 200, true)));
+        assert_eq!(resolve("bytes=-100"), Some((900, FULL_LEN, true)));
+
+        // the whole thing, asked for explicitly
+        assert_eq!(resolve("bytes=0-999"), Some((0, FULL_LEN, true)));
+
+        // several ranges are left to `select_ranges`
+        assert_eq!(resolve("bytes=0-9, 20-29"), None);
+
+        // ranges with nothing in them
+        assert_eq!(resolve("bytes=1000-"), None);
+        assert_eq!(resolve("bytes=-0"), None);
+    }
+
+    /// Make sure ranges outside the upload, or that can't be parsed,
+    /// aren't answered with the whole upload
+    #[test]
+    fn resolve_bad_ranges() {
+        // out of bounds
+        assert_eq!(resolve("bytes=2000-2999"), None);
+        assert_eq!(resolve("bytes=500-1999"), None);
+
+        // malformed
+        assert_eq!(resolve("bytes=abc"), None);
+        assert_eq!(resolve("bytes=10-5"), None);
+        assert_eq!(resolve("bytes="), None);
+
+        // a suffix longer than the upload asks for all of it
+        assert_eq!(resolve("bytes=-2000"), Some((0, FULL_LEN, true)));
+    }
+
+    /// Make sure links are relative when there's no base URL
+    #[tokio::test]
+    async fn relative_urls() {
+        let mut engine = test_engine("relative_urls", r#"deletion_secret = "abc""#);
+        engine.cfg.base_url = String::new();
+
+        assert_eq!(engine.view_url("abcdef.png"), "/p/abcdef.png");
+        let deletion_url = engine.deletion_url("abcdef.png", 0).unwrap();
+        assert!(deletion_url.starts_with("/del?name=abcdef.png&"));
+
+        std::fs::remove_dir_all(test_dir("relative_urls")).unwrap();
+    }
+
+    #[test]
+    fn name_alphabets() {
+        let name = random_name(NameAlphabet::Unambiguous, 1000);
+        assert_eq!(name.len(), 1000);
+        assert!(name.bytes().all(|c| UNAMBIGUOUS_ALPHABET.contains(&c)));
+        assert!(!name.contains(['i', 'l', 'o', 'u']));
+
+        let name = random_name(NameAlphabet::Alphanumeric, 1000);
+        assert_eq!(name.len(), 1000);
+        assert!(name.bytes().all(|c| c.is_ascii_alphanumeric()));
+    }
+
+    /// Make sure requests for several ranges are split into parts,
+    /// leaving out the ones that can't be satisfied
+    #[test]
+    fn select_several_ranges() {
+        let select = |range: &'static str| {
+            let value = HeaderValue::from_static(range);
+            let range = Range::decode(&mut std::iter::once(&value)).unwrap();
+            select_ranges(Some(range), FULL_LEN)
+        };
+
+        // kept in the order they were asked for
+        assert_eq!(
+            select("bytes=500-599, 0-9, -100"),
+            Some(Selection::Multi(vec![(500, 600), (0, 10), (900, FULL_LEN)]))
+        );
+
+        // one range is still sent the usual way
+        assert_eq!(select("bytes=0-9"), Some(Selection::Single(0, 10, true)));
+        assert_eq!(
+            select("bytes=0-9, 2000-2999"),
+            Some(Selection::Single(0, 10, true))
+        );
+
+        // nothing that can be satisfied
+        assert_eq!(select("bytes=1000-1999, 2000-2999"), None);
+        assert_eq!(
+            select_ranges(None, FULL_LEN),
+            Some(Selection::Single(0, FULL_LEN, false))
+        );
+    }
+
+    /// Make sure ranges are counted, without needing the upload's length
+    #[test]
+    fn count_ranges() {
+        let decode = |range: &'static str| {
+            let value = HeaderValue::from_static(range);
+            Range::decode(&mut std::iter::once(&value)).unwrap()
+        };
+
+        assert!(!too_many_ranges(&decode("bytes=0-9"), 1));
+        assert!(too_many_ranges(&decode("bytes=0-9, 20-29"), 1));
+        assert!(!too_many_ranges(&decode("bytes=0-9, 20-29, -5"), 3));
+        assert!(too_many_ranges(&decode("bytes=0-0, 1-1, 2-2, 3-3"), 3));
+    }
+
+    /// Make sure empty uploads are served whole,
+    /// and any range of one is unsatisfiable instead of underflowing
+    #[tokio::test]
+    async fn empty_upload() {
+        let engine = test_engine("empty_upload", "");
+        let saved_name = "empty-test.txt".to_string();
+        let path = test_dir("empty_upload").join(&saved_name);
+        std::fs::write(&path, b"").unwrap();
+
+        // first from disk, then from the cache
+        for _ in 0..2 {
+            let Ok(GetOutcome::Success(res)) = engine.get(&saved_name, None).await else {
+                panic!("empty upload was not found");
+            };
+            let res = res.into_response();
+            assert_eq!(res.status(), http::StatusCode::OK);
+            assert_eq!(res.headers()["Content-Length"], "0");
+
+            for range in ["bytes=0-", "bytes=0-0", "bytes=-0"] {
+                let value = HeaderValue::from_static(range);
+                let range = Range::decode(&mut std::iter::once(&value)).unwrap();
+                let outcome = engine.get(&saved_name, Some(range)).await.unwrap();
+                assert!(matches!(outcome, GetOutcome::RangeNotSatisfiable(Some(0))));
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        std::fs::remove_dir_all(test_dir("empty_upload")).unwrap();
+    }
+
+    /// Make sure gzipped uploads are decoded
+    #[tokio::test]
+    async fn decode_gzip() {
+        let original = b"hello hello hello hello hello".repeat(64);
+
+        let mut compressed = Vec::new();
+        GzipEncoder::new(&original[..])
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+        assert!(compressed.len() < original.len());
+
+        let stream = Body::from(compressed).into_data_stream();
+        let mut stream = decode_stream(stream, UploadEncoding::Gzip);
+
+        let mut decoded = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            decoded.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(decoded, original);
+    }
+
+    /// Make sure deletion hashes don't change between versions,
+    /// since that would break existing deletion urls
+    #[test]
+    fn deletion_hash_is_stable() {
+        let sample = HashSample {
+            head: Bytes::from_static(b"hello world"),
+            tail: None,
+        };
+        let hash = calculate_hash(11, &sample);
+        assert_eq!(hash, 0x78fcc65f124805e7d8da6a8230bb0b0a);
+    }
+
+    /// Make sure sampling the end tells apart uploads
+    /// that only differ after the start
+    #[test]
+    fn deletion_hash_tail() {
+        let sample = |tail: &'static [u8]| HashSample {
+            head: Bytes::from_static(b"same start"),
+            tail: Some(Bytes::from_static(tail)),
+        };
+
+        assert_ne!(
+            calculate_hash(40_000, &sample(b"one end")),
+            calculate_hash(40_000, &sample(b"another end"))
+        );
+    }
+
+    /// Make sure deletion URLs made with an old secret
+    /// still work while it's listed after a new one
+    #[test]
+    fn deletion_secret_rotation() {
+        let hmac_of = |engine: &Engine| {
+            let url = engine.deletion_url("abcdef.png", 1234).unwrap();
+            let hmac = url.rsplit_once("hmac=").unwrap().1.to_string();
+            BASE64_URL_SAFE_NO_PAD.decode(hmac).unwrap()
+        };
+
+        let old = test_engine("deletion_secret_rotation-old", r#"deletion_secret = "old""#);
+        let rotated = test_engine(
+            "deletion_secret_rotation-rotated",
+            r#"deletion_secret = ["new", "old"]"#,
+        );
+        let new = test_engine("deletion_secret_rotation-new", r#"deletion_secret = "new""#);
+
+        let old_hmac = hmac_of(&old);
+        assert!(rotated.verify_deletion_hmac("abcdef.png", 1234, &old_hmac));
+        assert!(!new.verify_deletion_hmac("abcdef.png", 1234, &old_hmac));
+
+        // new urls are signed with the first secret
+        assert_eq!(hmac_of(&rotated), hmac_of(&new));
+        assert!(!rotated.verify_deletion_hmac("abcdef.png", 4321, &old_hmac));
+        std::fs::remove_dir_all(test_dir("deletion_secret_rotation-old")).unwrap();
+        std::fs::remove_dir_all(test_dir("deletion_secret_rotation-rotated")).unwrap();
+        std::fs::remove_dir_all(test_dir("deletion_secret_rotation-new")).unwrap();
+    }
+
+    /// Where the engine for the test `name` saves uploads
+    pub(crate) fn test_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("breeze-engine-{name}-{}", std::process::id()))
+    }
+
+    /// Build an engine with a minimal config, that saves to a fresh
+    /// directory for the test `name` (see [`test_dir`]).
+    /// `engine_extra` is added to the `[engine]` section
+    fn test_engine(name: &str, engine_extra: &str) -> Engine {
+        test_engine_with(name, engine_extra, "")
+    }
+
+    /// Like [`test_engine`], with `disk_extra` added to the `[disk]` section too
+    pub(crate) fn test_engine_with(name: &str, engine_extra: &str, disk_extra: &str) -> Engine {
+        let dir = test_dir(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let cfg: Config = toml::from_str(&format!(
+            r#"
+            [engine]
+            base_url = "http://127.0.0.1:8000"
+            max_temp_lifetime = 43200
+            max_strip_len = 16_777_215
+            {engine_extra}
+
+            [cache]
+            max_length = 1_000_000
+            default_residency = 1800
+            scan_freq = 60
+            mem_capacity = 10_000_000
+
+            [disk]
+            save_path = {:?}
+            {disk_extra}
+
+            [http]
+            listen_on = "127.0.0.1:8000"
+
+            [logger]
+            "#,
+            dir,
+        ))
+        .unwrap();
+
+        let cache = Arc::new(Cache::with_config(cfg.cache).unwrap());
+        let disk = Disk::with_config(cfg.disk);
+        Engine::new(cfg.engine, &cfg.http, cache, disk).unwrap()
+    }
+
+    /// Make sure ranges of cached uploads share
+    /// the cache entry's allocation instead of copying it
+    #[tokio::test]
+    async fn cache_range_is_zero_copy() {
+        let engine = test_engine("cache_range_is_zero_copy", "");
+        let data = Bytes::from(b"0123456789".repeat(100));
+        engine.cache.add("abcdef.txt", data.clone());
+
+        let value = HeaderValue::from_static("bytes=2-4");
+        let range = Range::decode(&mut std::iter::once(&value)).unwrap();
+        let Ok(GetOutcome::Success(res)) = engine.get("abcdef.txt", Some(range)).await else {
+            panic!("cached upload was not found");
+        };
+        let UploadData::Cache(sent) = res.data else {
+            panic!("cached upload was not sent from cache");
+        };
+
+        assert_eq!(&sent[..], b"234");
+        assert_eq!(sent.as_ptr(), data[2..].as_ptr());
+
+        std::fs::remove_dir_all(test_dir("cache_range_is_zero_copy")).unwrap();
+    }
+
+    /// Make sure uploads under `full_hash_below` are hashed whole,
+    /// so ones that only differ after the sample don't match
+    #[tokio::test]
+    async fn full_hash_below() {
+        let mut a = vec![0; 40_000];
+        let b = a.clone();
+        a[35_000] = 1;
+
+        for (full_hash_below, same) in [("", true), ("full_hash_below = 65536", false)] {
+            let engine = test_engine("full_hash_below", full_hash_below);
+            engine.cache.add("aaaaaa.bin", Bytes::from(a.clone()));
+            engine.cache.add("bbbbbb.bin", Bytes::from(b.clone()));
+
+            let hash_a = engine.get_hash("aaaaaa.bin").await.unwrap();
+            let hash_b = engine.get_hash("bbbbbb.bin").await.unwrap();
+            assert_eq!(hash_a == hash_b, same);
+        }
+
+        std::fs::remove_dir_all(test_dir("full_hash_below")).unwrap();
+    }
+
+    /// Make sure temporary uploads are described from the cache,
+    /// with when they expire
+    #[tokio::test]
+    async fn info_temporary() {
+        let engine = test_engine("info_temporary", "");
+        let lifetime = Duration::from_secs(60);
+        engine
+            .cache
+            .add_temporary("tmpinf.txt", Bytes::from_static(b"hello"), lifetime);
+
+        let info = engine.info("tmpinf.txt").await.unwrap().unwrap();
+        assert_eq!(info.length, 5);
+        assert!(info.cached);
+
+        // it expires a lifetime after it was added
+        let expires = info.expires.unwrap();
+        assert!(expires >= info.created.unwrap() + lifetime);
+        assert!(expires <= SystemTime::now() + lifetime);
+
+        assert!(engine.info("nothere.txt").await.unwrap().is_none());
+
+        std::fs::remove_dir_all(test_dir("info_temporary")).unwrap();
+    }
+
+    /// Make sure only pastes can be followed, and that
+    /// following one sends what it has and then what's appended
+    #[tokio::test]
+    async fn follow_paste() {
+        let engine = test_engine("follow_paste", r#"deletion_secret = "abc""#);
+
+        // an upload that isn't a paste
+        let saved_name = "follow-test.txt".to_string();
+        let path = test_dir("follow_paste").join(&saved_name);
+        std::fs::write(&path, b"hello").unwrap();
+        assert!(engine.follow(&saved_name).await.unwrap().is_none());
+        std::fs::remove_file(&path).unwrap();
+
+        let Ok(CreatePasteOutcome::Success { append_url, .. }) = engine.create_paste().await else {
+            panic!("paste was not created");
+        };
+        let (saved_name, token) = append_url
+            .rsplit_once("/paste/")
+            .and_then(|(_, rest)| rest.split_once("?token="))
+            .unwrap();
+
+        let append = async |data: &'static str| {
+            let outcome = engine.append(saved_name, token, Bytes::from_static(data.as_bytes()));
+            assert!(matches!(outcome.await.unwrap(), AppendOutcome::Success));
+        };
+        append("first\n").await;
+
+        let mut stream = std::pin::pin!(engine.follow(saved_name).await.unwrap().unwrap());
+        assert_eq!(stream.next().await.unwrap().unwrap(), "first\n");
+
+        append("second\n").await;
+        assert_eq!(stream.next().await.unwrap().unwrap(), "second\n");
+
+        engine.remove(saved_name).await.unwrap();
+
+        // a good token for a paste that's gone doesn't bring it back
+        let outcome = engine.append(saved_name, token, Bytes::from_static(b"third\n"));
+        assert!(matches!(outcome.await.unwrap(), AppendOutcome::NotFound));
+        assert!(!engine.pastes.contains_key(saved_name));
+
+        std::fs::remove_dir_all(test_dir("follow_paste")).unwrap();
+    }
+
+    /// Make sure uploads can be made permanent and temporary again,
+    /// moving between the cache and disk, and deleted by an expiry that's passed
+    #[tokio::test]
+    async fn change_lifetime() {
+        let engine = test_engine("change_lifetime", "");
+        let saved_name = "lifetime-test.txt".to_string();
+        let path = test_dir("change_lifetime").join(&saved_name);
+        let lifetime = Duration::from_secs(60);
+        engine
+            .cache
+            .add_temporary(&saved_name, Bytes::from_static(b"hello"), lifetime);
+
+        // as if it was uploaded a while ago
+        let created = SystemTime::now() - Duration::from_secs(3600);
+        engine.cache.set_created(&saved_name, created);
+
+        // too long, or not there at all
+        let too_long = Some(Duration::from_secs(43201));
+        assert!(matches!(
+            engine.set_lifetime(&saved_name, too_long).await.unwrap(),
+            LifetimeOutcome::LifetimeTooLong
+        ));
+        assert!(matches!(
+            engine.set_lifetime("nothere.txt", None).await.unwrap(),
+            LifetimeOutcome::NotFound
+        ));
+
+        // temporary to permanent saves it to disk
+        assert!(matches!(
+            engine.set_lifetime(&saved_name, None).await.unwrap(),
+            LifetimeOutcome::Changed
+        ));
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+        assert!(
+            !path
+                .with_file_name(format!(".permanent-{saved_name}"))
+                .exists()
+        );
+        assert!(engine.cache.expires_at(&saved_name).is_none());
+
+        // and back, which takes it off disk
+        assert!(matches!(
+            engine
+                .set_lifetime(&saved_name, Some(lifetime))
+                .await
+                .unwrap(),
+            LifetimeOutcome::Changed
+        ));
+        assert!(!path.exists());
+        assert_eq!(engine.cache.created(&saved_name), Some(created));
+
+        // the new lifetime counts from now, not from when it was uploaded
+        let expires_at = engine.cache.expires_at(&saved_name).unwrap();
+        assert!(expires_at > SystemTime::now() + lifetime - Duration::from_secs(5));
+
+        // an expiry that's already passed deletes it
+        assert!(matches!(
+            engine
+                .set_lifetime(&saved_name, Some(Duration::ZERO))
+                .await
+                .unwrap(),
+            LifetimeOutcome::Deleted
+        ));
+        assert!(!engine.has(&saved_name).await);
+
+        std::fs::remove_dir_all(test_dir("change_lifetime")).unwrap();
+    }
+
+    /// Make sure replacing an upload swaps out its data
+    /// on disk and in the cache, under the same name
+    #[tokio::test]
+    async fn replace_keeps_name() {
+        let engine = test_engine("replace_keeps_name", "");
+        let saved_name = "replace-test.txt".to_string();
+        let path = test_dir("replace_keeps_name").join(&saved_name);
+        std::fs::write(&path, b"old data").unwrap();
+        engine
+            .cache
+            .add(&saved_name, Bytes::from_static(b"old data"));
+
+        let stream = Body::from("new data!").into_data_stream();
+        let outcome = engine
+            .replace(&saved_name, 9, stream, UploadOptions::default())
+            .await
+            .unwrap();
+        let ProcessOutcome::Success { url, .. } = outcome else {
+            panic!("upload was not replaced");
+        };
+
+        let on_disk = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(url.ends_with(&saved_name));
+        assert_eq!(on_disk, b"new data!");
+        assert_eq!(&engine.cache.get(&saved_name).unwrap()[..], b"new data!");
+        assert!(
+            !std::env::temp_dir()
+                .join(format!(".replacing-{saved_name}"))
+                .exists()
+        );
+
+        std::fs::remove_dir_all(test_dir("replace_keeps_name")).unwrap();
+    }
+
+    /// Make sure uploads that ask to skip the cache only go to disk,
+    /// unless they're temporary
+    #[tokio::test]
+    async fn no_cache() {
+        let engine = test_engine("no_cache", "");
+        let saved_name = "nocache-test.txt".to_string();
+        let opts = UploadOptions {
+            no_cache: true,
+            ..Default::default()
+        };
+
+        let stream = Body::from("backup").into_data_stream();
+        let outcome = engine
+            .process_named(&saved_name, 6, stream, opts)
+            .await
+            .unwrap();
+        assert!(matches!(outcome, ProcessOutcome::Success { .. }));
+        engine.wait_for_writes().await;
+
+        let path = test_dir("no_cache").join(&saved_name);
+        let on_disk = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(on_disk, b"backup");
+        assert!(!engine.cache.has(&saved_name));
+
+        let opts = UploadOptions {
+            lifetime: Some(Duration::from_secs(60)),
+            ..opts
+        };
+        assert!(engine.should_cache(Some("txt"), 6, &opts));
+
+        std::fs::remove_dir_all(test_dir("no_cache")).unwrap();
+    }
+
+    /// Make sure uploads that ask not to be deletable
+    /// aren't given a deletion URL
+    #[tokio::test]
+    async fn no_delete() {
+        let engine = test_engine("no_delete", r#"deletion_secret = "abc""#);
+        let upload = async |saved_name: &str, no_delete| {
+            let opts = UploadOptions {
+                no_delete,
+                lifetime: Some(Duration::from_secs(60)),
+                ..Default::default()
+            };
+            let stream = Body::from("hello").into_data_stream();
+            match engine.process_named(saved_name, 5, stream, opts).await {
+                Ok(ProcessOutcome::Success { deletion_url, .. }) => deletion_url,
+                _ => panic!("upload failed"),
+            }
+        };
+
+        assert!(upload("nodelete-a.txt", false).await.is_some());
+        assert!(upload("nodelete-b.txt", true).await.is_none());
+
+        std::fs::remove_dir_all(test_dir("no_delete")).unwrap();
+    }
+
+    /// Make sure upload sizes are counted in the right buckets,
+    /// whatever order the bounds are given in
+    #[tokio::test]
+    async fn size_buckets() {
+        let engine = test_engine("size_buckets", "upload_size_buckets = [100, 10, 100]");
+
+        for len in [0, 10, 11, 100, 101, u64::MAX] {
+            engine.count_size(len);
+        }
+
+        let stats = engine.stats().await.unwrap();
+        assert_eq!(
+            stats.upload_sizes,
+            vec![(Some(10), 2), (Some(100), 2), (None, 2)]
+        );
+
+        std::fs::remove_dir_all(test_dir("size_buckets")).unwrap();
+    }
+
+    /// Make sure uploads can be stored under a given name,
+    /// but never over one that's taken
+    #[tokio::test]
+    async fn process_named_no_overwrite() {
+        let engine = test_engine("process_named_no_overwrite", "");
+        let saved_name = "named-test.txt".to_string();
+        let path = test_dir("process_named_no_overwrite").join(&saved_name);
+
+        let stream = Body::from("first").into_data_stream();
+        let outcome = engine
+            .process_named(&saved_name, 5, stream, UploadOptions::default())
+            .await
+            .unwrap();
+        let ProcessOutcome::Success { url, .. } = outcome else {
+            panic!("upload was not stored");
+        };
+        assert!(url.ends_with(&saved_name));
+
+        let stream = Body::from("second").into_data_stream();
+        let outcome = engine
+            .process_named(&saved_name, 6, stream, UploadOptions::default())
+            .await
+            .unwrap();
+
+        assert!(matches!(outcome, ProcessOutcome::AlreadyInProgress));
+        assert_eq!(&engine.cache.get(&saved_name).unwrap()[..], b"first");
+
+        // only the upload that was stored is counted
+        assert_eq!(*engine.ext_uploads.get("txt").unwrap(), (1, 5));
+
+        // the disk write finishes in the background
+        engine.wait_for_writes().await;
+        let on_disk = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(on_disk, b"first");
+
+        std::fs::remove_dir_all(test_dir("process_named_no_overwrite")).unwrap();
     }
 }
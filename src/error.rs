@@ -0,0 +1,129 @@
+use axum::{
+    Json,
+    body::to_bytes,
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{StatusCode, header};
+use serde::Serialize;
+
+/// Error bodies bigger than this are left alone
+const MAX_ERROR_LEN: usize = 4096;
+
+/// An error response body, for clients that asked for JSON
+#[derive(Serialize)]
+struct JsonError {
+    /// Machine-readable error code, like `not_found`
+    error: String,
+
+    /// The message that would otherwise be sent as plain text
+    message: String,
+}
+
+/// Turn a status code into an error code, like `Not Found` -> `not_found`
+fn error_code(status: StatusCode) -> String {
+    status
+        .canonical_reason()
+        .unwrap_or("unknown")
+        .to_ascii_lowercase()
+        .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+}
+
+/// Middleware that sends plain-text error responses as JSON instead,
+/// if the client sent `Accept: application/json`.
+///
+/// Everything else (and clients that didn't ask) is left as is.
+pub async fn json_errors(req: Request, next: Next) -> Response {
+    let wants_json = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("application/json"));
+
+    let res = next.run(req).await;
+
+    let status = res.status();
+    let is_plain_text = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/plain"));
+    if !wants_json || !is_plain_text || !(status.is_client_error() || status.is_server_error()) {
+        return res;
+    }
+
+    let (parts, body) = res.into_parts();
+    let Ok(message) = to_bytes(body, MAX_ERROR_LEN).await else {
+        return (parts.status, "Error body too large").into_response();
+    };
+
+    let body = JsonError {
+        error: error_code(status),
+        message: String::from_utf8_lossy(&message).into_owned(),
+    };
+
+    // keep other headers (like retry-after), but not ones describing the old body
+    let mut res = (status, Json(body)).into_response();
+    for (name, value) in parts.headers.iter() {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            res.headers_mut().append(name, value.clone());
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, body::Body, middleware, routing::get};
+    use http::{Request, StatusCode, header};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::{error_code, json_errors};
+
+    /// Make sure error codes are formatted properly
+    #[test]
+    fn codes() {
+        assert_eq!(error_code(StatusCode::NOT_FOUND), "not_found");
+        assert_eq!(
+            error_code(StatusCode::TOO_MANY_REQUESTS),
+            "too_many_requests"
+        );
+        assert_eq!(error_code(StatusCode::IM_A_TEAPOT), "i_m_a_teapot");
+    }
+
+    /// Make sure errors are only sent as JSON when it was asked for
+    #[tokio::test]
+    async fn only_when_asked() {
+        let app = Router::new()
+            .route("/", get(|| async { (StatusCode::NOT_FOUND, "Not found!") }))
+            .layer(middleware::from_fn(json_errors));
+
+        let get = |accept: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = Request::get("/")
+                    .header(header::ACCEPT, accept)
+                    .body(Body::empty())
+                    .unwrap();
+                let res = app.oneshot(req).await.unwrap();
+                let status = res.status();
+                let body = res.into_body().collect().await.unwrap().to_bytes();
+                (status, body)
+            }
+        };
+
+        let (status, body) = get("text/html").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(&body[..], b"Not found!");
+
+        let (status, body) = get("application/json").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            &body[..],
+            br#"{"error":"not_found","message":"Not found!"}"#
+        );
+    }
+}
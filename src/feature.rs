@@ -0,0 +1,30 @@
+use http::StatusCode;
+
+/// Optional features that need extra configuration before they can be used
+#[derive(Clone, Copy)]
+pub enum Feature {
+    /// Deletion URLs, enabled by `engine.deletion_secret`
+    Deletion,
+
+    /// Admin endpoints, enabled by `engine.admin_key`
+    Admin,
+
+    /// Appendable pastes, enabled by `engine.deletion_secret`
+    Paste,
+}
+
+impl Feature {
+    /// The rejection sent back when this feature is used while it isn't configured.
+    ///
+    /// This is always 409 Conflict, so disabled features can be told apart
+    /// from missing files (404) or actual failures (500).
+    pub fn disabled(self) -> (StatusCode, &'static str) {
+        let msg = match self {
+            Feature::Deletion => "Deletion is not enabled",
+            Feature::Admin => "Admin endpoints are not enabled",
+            Feature::Paste => "Pastes are not enabled",
+        };
+
+        (StatusCode::CONFLICT, msg)
+    }
+}
@@ -0,0 +1,50 @@
+use sha2::{Digest, Sha256};
+use twox_hash::XxHash3_128;
+
+/// A hash algorithm uploads can be fed through, piece by piece.
+///
+/// Which algorithm is used for what is picked with the aliases below,
+/// so new ones can be added without touching the code that hashes.
+pub trait UploadHasher: Default {
+    /// What a finished hash looks like
+    type Output;
+
+    /// Feed more data into the hash
+    fn update(&mut self, data: &[u8]);
+
+    /// Finish hashing
+    fn finish(self) -> Self::Output;
+}
+
+impl UploadHasher for XxHash3_128 {
+    type Output = u128;
+
+    fn update(&mut self, data: &[u8]) {
+        self.write(data);
+    }
+
+    fn finish(self) -> u128 {
+        self.finish_128()
+    }
+}
+
+impl UploadHasher for Sha256 {
+    type Output = [u8; 32];
+
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn finish(self) -> [u8; 32] {
+        self.finalize().into()
+    }
+}
+
+/// What deletion URLs are made from.
+///
+/// This should not change between versions!!
+/// That would break existing deletion urls
+pub type DeletionHasher = XxHash3_128;
+
+/// What uploads are checked against client-given digests with
+pub type DigestHasher = Sha256;
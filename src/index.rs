@@ -1,27 +1,146 @@
-use std::sync::{Arc, atomic::Ordering};
+use std::{
+    io,
+    path::Path,
+    sync::{Arc, atomic::Ordering},
+};
 
-use axum::extract::State;
+use axum::{
+    Json,
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, StatusCode, header};
+use serde::{Deserialize, Serialize};
+use tracing::error;
 
 use crate::engine::Engine;
 
-/// Show index status page with amount of uploaded files
-pub async fn index(State(engine): State<Arc<Engine>>) -> String {
-    let count = engine.upl_count.load(Ordering::Relaxed);
+#[derive(Deserialize)]
+pub struct IndexRequest {
+    format: Option<String>,
+}
+
+/// A short status summary, for scripts that just want the numbers
+#[derive(Serialize)]
+pub struct IndexStatus {
+    /// Current breeze version
+    version: &'static str,
+
+    /// Number of uploads, counted the same way as the motd's `%uplcount%`
+    uploads: usize,
+
+    /// Total length of uploads in the cache (in bytes)
+    cache_bytes: usize,
+
+    /// Number of uploads in the cache
+    cache_entries: usize,
+
+    /// Total length of uploads saved to disk (in bytes)
+    disk_bytes: u64,
+}
+
+/// Show index status page with amount of uploaded files.
+///
+/// Clients asking for JSON (`?format=json` or `Accept: application/json`)
+/// get a short status summary instead of the motd.
+pub async fn index(
+    State(engine): State<Arc<Engine>>,
+    headers: HeaderMap,
+    Query(req): Query<IndexRequest>,
+) -> Response {
+    let temp_count = engine.temp_count();
+    let mut count = engine.upl_count.load(Ordering::Relaxed);
+    if engine.cfg.count_temp_uploads {
+        count += temp_count;
+    }
+
+    let wants_json = req.format.as_deref() == Some("json")
+        || headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("application/json"));
+    if wants_json {
+        // the disk usage summary is reused for a while, so this stays cheap
+        let stats = match engine.stats().await {
+            Ok(stats) => stats,
+            Err(err) => {
+                error!("failed to get stats!! {err:#}");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error!")
+                    .into_response();
+            }
+        };
+
+        return Json(IndexStatus {
+            version: env!("CARGO_PKG_VERSION"),
+            uploads: count,
+            cache_bytes: stats.cache_length,
+            cache_entries: stats.cache_count,
+            disk_bytes: stats.disk_usage.length,
+        })
+        .into_response();
+    }
 
     let motd = engine.cfg.motd.clone();
 
     motd.replace("%version%", env!("CARGO_PKG_VERSION"))
         .replace("%uplcount%", &count.to_string())
+        .replace("%tempcount%", &temp_count.to_string())
+        .into_response()
 }
 
-#[rustfmt::skip]
-pub async fn robots_txt() -> &'static str {
-    /// robots.txt that tells web crawlers not to list uploads
-    const ROBOTS_TXT: &str = concat!(
-        "User-Agent: *\n",
-        "Disallow: /p/*\n",
-        "Allow: /\n"
+/// robots.txt that tells web crawlers not to list uploads
+pub async fn robots_txt(State(engine): State<Arc<Engine>>) -> String {
+    format!(
+        "User-Agent: *\nDisallow: {}/*\nAllow: /\n",
+        engine.routes.view
+    )
+}
+
+/// An icon to serve at /favicon.ico
+#[derive(Clone)]
+pub struct Favicon {
+    data: Bytes,
+    content_type: &'static str,
+}
+
+impl Favicon {
+    /// Read an icon from disk, guessing its content type from its extension
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = Bytes::from(std::fs::read(path)?);
+
+        let content_type = match path.extension().and_then(|e| e.to_str()) {
+            Some("png") => "image/png",
+            Some("svg") => "image/svg+xml",
+            Some("gif") => "image/gif",
+            _ => "image/x-icon",
+        };
+
+        Ok(Self { data, content_type })
+    }
+}
+
+/// Serve the favicon, if one is configured.
+///
+/// If not, 204 No Content is sent instead of 404 so browsers
+/// asking for it don't clutter up logs.
+pub async fn favicon(icon: Option<Favicon>) -> Response {
+    let Some(icon) = icon else {
+        return StatusCode::NO_CONTENT.into_response();
+    };
+
+    let mut res = icon.data.into_response();
+    let headers = res.headers_mut();
+
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(icon.content_type),
+    );
+    // icons rarely change, so let browsers hold onto it for a day
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("public, max-age=86400"),
     );
 
-    ROBOTS_TXT
+    res
 }
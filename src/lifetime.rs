@@ -0,0 +1,121 @@
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::extract::{ConnectInfo, Query, State};
+use http::{HeaderMap, StatusCode};
+use serde::Deserialize;
+use serde_with::{DurationSeconds, serde_as};
+use tracing::error;
+
+use crate::{
+    admin::check_key,
+    audit::AuditAction,
+    delete::{check_token, token_from_header},
+    engine::{Engine, LifetimeOutcome},
+    new::parse_expiry,
+};
+
+#[serde_as]
+#[derive(Deserialize)]
+pub struct LifetimeRequest {
+    name: String,
+
+    /// How long from now the upload should last
+    #[serde(rename = "lastfor")]
+    #[serde_as(as = "Option<DurationSeconds>")]
+    last_for: Option<Duration>,
+
+    /// When the upload should expire, instead of `lastfor`
+    /// (RFC 3339 or a unix timestamp)
+    expires_at: Option<String>,
+
+    hash: Option<String>,
+    hmac: Option<String>,
+    key: Option<String>,
+}
+
+/// Make sure the client is allowed to change an upload's lifetime.
+/// This needs its deletion token (from the query or headers), or the admin key.
+async fn check_auth(
+    engine: &Engine,
+    headers: &HeaderMap,
+    req: &LifetimeRequest,
+) -> Result<(), (StatusCode, &'static str)> {
+    if req.key.is_some() {
+        return check_key(engine, req.key.clone());
+    }
+
+    let token = token_from_header(headers).or_else(|| req.hash.clone().zip(req.hmac.clone()));
+    match token {
+        Some((hash, hmac)) if engine.deletion_hmac.is_some() => {
+            check_token(engine, &req.name, &hash, &hmac).await
+        }
+        _ => Err((
+            StatusCode::FORBIDDEN,
+            "Changing an upload's lifetime needs its deletion token or the admin key",
+        )),
+    }
+}
+
+/// The request handler for the /lifetime path.
+///
+/// With `lastfor` or `expires_at`, the upload becomes temporary and expires then.
+/// With neither, it becomes permanent. An expiry that has already passed deletes it.
+pub async fn set_lifetime(
+    State(engine): State<Arc<Engine>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(req): Query<LifetimeRequest>,
+) -> (StatusCode, &'static str) {
+    if let Err(rejection) = check_auth(&engine, &headers, &req).await {
+        return rejection;
+    }
+
+    // an expiry time can be given instead of a lifetime.
+    // if both are, the lifetime wins
+    let lifetime = match (req.last_for, req.expires_at.as_deref()) {
+        (None, Some(at)) => {
+            let Some(at) = parse_expiry(at) else {
+                return (StatusCode::BAD_REQUEST, "Expiry time is invalid");
+            };
+            Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+        }
+        (last_for, _) => last_for,
+    };
+
+    match engine.set_lifetime(&req.name, lifetime).await {
+        Ok(LifetimeOutcome::Changed) if lifetime.is_some() => {
+            (StatusCode::OK, "Upload is now temporary")
+        }
+        Ok(LifetimeOutcome::Changed) => (StatusCode::OK, "Upload is now permanent"),
+        Ok(LifetimeOutcome::Deleted) => {
+            engine.audit(AuditAction::Delete, &req.name, None, Some(addr.ip()));
+
+            (
+                StatusCode::OK,
+                "Upload's new expiry has already passed, so it was deleted",
+            )
+        }
+        Ok(LifetimeOutcome::NotFound) => (StatusCode::NOT_FOUND, "Upload not found"),
+        Ok(LifetimeOutcome::InProgress) => (StatusCode::CONFLICT, "Upload is still being saved"),
+        Ok(LifetimeOutcome::TooLarge) => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "Upload is too large to be temporary",
+        ),
+        Ok(LifetimeOutcome::LifetimeTooLong) => (
+            StatusCode::BAD_REQUEST,
+            "Lifetime is longer than the server allows",
+        ),
+        Ok(LifetimeOutcome::DiskFull) => (
+            StatusCode::INSUFFICIENT_STORAGE,
+            "There's no room on disk to keep the upload",
+        ),
+        Err(err) => {
+            error!("failed to change upload lifetime!! {err:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error!")
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use axum::{Json, extract::State};
+use serde::Serialize;
+
+use crate::engine::Engine;
+
+/// Limits that uploads are checked against,
+/// so clients can check uploads before sending them
+#[derive(Serialize)]
+pub struct Limits {
+    /// Maximum length of an upload (in bytes), if there is one
+    max_upload_len: Option<u64>,
+
+    /// Minimum length of an upload (in bytes), if there is one
+    min_upload_len: Option<u64>,
+
+    /// Maximum lifetime of a temporary upload (in seconds)
+    max_temp_lifetime: u64,
+
+    /// Maximum length of a temporary upload (in bytes)
+    max_temp_len: usize,
+
+    /// Whether an upload key is needed to upload
+    key_required: bool,
+}
+
+/// The request handler for the /limits path.
+pub async fn limits(State(engine): State<Arc<Engine>>) -> Json<Limits> {
+    Json(Limits {
+        max_upload_len: engine.cfg.max_upload_len,
+        min_upload_len: engine.cfg.min_upload_len,
+        max_temp_lifetime: engine.cfg.max_temp_lifetime.as_secs(),
+        max_temp_len: engine.max_cached_len(),
+        key_required: !engine.cfg.upload_key.is_empty(),
+    })
+}
@@ -1,55 +1,203 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    path::PathBuf,
+    sync::{Arc, atomic::Ordering},
+};
 
 use argh::FromArgs;
 use color_eyre::eyre::{self, Context, bail};
 use engine::Engine;
 
 use axum::{
-    Router,
-    routing::{get, post},
+    Router, middleware,
+    response::Response,
+    routing::{delete, get, patch, post, put},
 };
-use tokio::{fs, net::TcpListener, signal};
+use http::{HeaderValue, header};
+use tokio::{net::TcpListener, runtime, signal};
+use tower_http::{limit::RequestBodyLimitLayer, services::ServeDir};
 use tracing::{info, warn};
 
+mod admin;
+mod audit;
 mod cache;
 mod config;
 mod delete;
 mod disk;
 mod engine;
+mod error;
+mod feature;
+mod hash;
 mod index;
+mod lifetime;
+mod limits;
+mod mime;
 mod new;
+mod paste;
+mod ratelimit;
+mod read;
+mod ready;
+mod serve;
+mod stats;
 mod view;
 
-#[cfg(not(target_env = "msvc"))]
+#[cfg(all(
+    feature = "jemalloc",
+    not(feature = "system-alloc"),
+    not(target_env = "msvc")
+))]
 use tikv_jemallocator::Jemalloc;
 
-use crate::{cache::Cache, disk::Disk};
+use crate::{cache::Cache, disk::Disk, index::Favicon};
 
-#[cfg(not(target_env = "msvc"))]
+#[cfg(all(
+    feature = "jemalloc",
+    not(feature = "system-alloc"),
+    not(target_env = "msvc")
+))]
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
 /// breeze file server.
 #[derive(FromArgs, Debug)]
 struct Args {
-    /// the path to *.toml configuration file
+    /// the path to *.toml configuration file, or - to read it from stdin
+    /// (default: /etc/breeze/config.toml)
     #[argh(option, short = 'c', arg_name = "file")]
-    config: PathBuf,
+    config: Option<PathBuf>,
 }
 
+/// Where the config file is read from if `--config` isn't given
+const DEFAULT_CONFIG_PATH: &str = "/etc/breeze/config.toml";
+
+/// How big a request body can be on routes that don't take uploads
+const SMALL_BODY_LIMIT: usize = 16 * 1024;
+
 /// Instantiates router.
-fn router(engine: Engine) -> Router {
-    Router::new()
-        .route("/new", post(new::new))
-        .route("/p/{saved_name}", get(view::view))
-        .route("/del", get(delete::delete))
-        .route("/", get(index::index))
-        .route("/robots.txt", get(index::robots_txt))
-        .with_state(Arc::new(engine))
+fn router(engine: Arc<Engine>, http: &config::HttpConfig, favicon: Option<Favicon>) -> Router {
+    // routes that shouldn't be used until the server is ready
+    let routes = &engine.routes;
+
+    // reject oversized bodies before they reach handlers.
+    // routes that take uploads are allowed up to `max_upload_len`
+    let upload_limit = RequestBodyLimitLayer::new(
+        engine
+            .cfg
+            .max_upload_len
+            .map_or(usize::MAX, |l| l.try_into().unwrap_or(usize::MAX)),
+    );
+    let small_limit = RequestBodyLimitLayer::new(SMALL_BODY_LIMIT);
+
+    // tools that speak a little WebDAV can store and remove uploads
+    // on the same path they're viewed from
+    let mut view_route = get(view::view).layer(small_limit);
+    if http.webdav {
+        view_route = view_route
+            .merge(put(new::put).layer(upload_limit))
+            .merge(delete(delete::delete_by_header).layer(small_limit));
+    }
+
+    let gated = Router::new()
+        .route(
+            &routes.new,
+            post(new::new).options(new::options).layer(upload_limit),
+        )
+        .route(&format!("{}/{{saved_name}}", routes.view), view_route)
+        .route(
+            &format!("{}/{{saved_name}}/{{file_name}}", routes.view),
+            get(view::view).layer(small_limit),
+        )
+        .route("/paste", post(paste::create).layer(small_limit))
+        .route(
+            "/paste/{saved_name}",
+            patch(paste::append).layer(upload_limit),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            engine.clone(),
+            ready::require_ready,
+        ));
+
+    let mut app = Router::new()
+        .route(
+            &routes.delete,
+            get(delete::delete)
+                .head(delete::delete_check)
+                .post(delete::delete_confirmed),
+        )
+        .route("/lifetime", post(lifetime::set_lifetime))
+        .route("/favicon.ico", get(move || index::favicon(favicon.clone())))
+        .route("/readyz", get(ready::readyz))
+        .route("/limits", get(limits::limits))
+        .route("/stats", get(stats::stats))
+        .route("/admin/cache/clear", post(admin::clear_cache))
+        .route("/admin/cache/evict", post(admin::evict_cache))
+        .route("/admin/cache/load", post(admin::load_cache))
+        .route("/admin/prune", post(admin::prune))
+        .route("/admin/deletion-url", get(admin::deletion_url))
+        .route("/admin/uploads", get(admin::uploads))
+        .route("/admin/uploads/cancel", post(admin::cancel_upload));
+
+    // these can be turned off so a reverse proxy can handle them instead
+    if http.enable_index {
+        app = app.route("/", get(index::index));
+    }
+    if http.enable_robots {
+        app = app.route("/robots.txt", get(index::robots_txt));
+    }
+    if let Some(ref dir) = http.static_dir {
+        app = app.nest_service(&http.routes.static_files, ServeDir::new(dir));
+    }
+
+    // everything else gets the small body limit,
+    // and errors are sent as json to clients that ask for it
+    let mut app = app
+        .layer(small_limit)
+        .merge(gated)
+        .layer(middleware::from_fn(error::json_errors));
+
+    // set the server header on everything, or take it away if it's empty
+    if let Some(ref server) = http.server_header {
+        let server = (!server.is_empty())
+            .then(|| HeaderValue::from_str(server).expect("server header was checked at startup"));
+        app = app.layer(middleware::map_response(move |mut res: Response| {
+            let server = server.clone();
+            async move {
+                match server {
+                    Some(server) => res.headers_mut().insert(header::SERVER, server),
+                    None => res.headers_mut().remove(header::SERVER),
+                };
+                res
+            }
+        }));
+    }
+
+    app.with_state(engine)
+}
+
+/// Read the config from the path given, stdin, or the default path
+fn read_config(path: Option<PathBuf>) -> eyre::Result<String> {
+    let path = match path {
+        // like most tools, `-` means stdin
+        Some(path) if path.as_os_str() == "-" => {
+            return std::io::read_to_string(std::io::stdin())
+                .wrap_err("failed to read config from stdin!");
+        }
+        Some(path) => path,
+        None => {
+            let path = PathBuf::from(DEFAULT_CONFIG_PATH);
+            if !path.exists() {
+                bail!(
+                    "no config file given! pass one with `--config <file>` (or `--config -` for stdin), or put it at {DEFAULT_CONFIG_PATH}"
+                );
+            }
+            path
+        }
+    };
+
+    std::fs::read_to_string(path)
+        .wrap_err("failed to read config file! make sure it exists and you have read permissions")
 }
 
-#[tokio::main]
-async fn main() -> eyre::Result<()> {
+fn main() -> eyre::Result<()> {
     // Install color-eyre
     color_eyre::install()?;
 
@@ -58,15 +206,37 @@ async fn main() -> eyre::Result<()> {
 
     // Read & parse config
     let cfg: config::Config = {
-        let config_str = fs::read_to_string(args.config).await.wrap_err(
-            "failed to read config file! make sure it exists and you have read permissions",
-        )?;
+        let config_str = read_config(args.config)?;
 
         toml::from_str(&config_str).wrap_err(
             "invalid config! ensure proper fields and structure. reference config is in readme",
         )?
     };
 
+    // Build the async runtime
+    if cfg.runtime.worker_threads == Some(0) || cfg.runtime.max_blocking_threads == Some(0) {
+        bail!("`runtime.worker_threads` and `runtime.max_blocking_threads` must be above 0");
+    }
+    let runtime = {
+        let mut builder = runtime::Builder::new_multi_thread();
+        builder.enable_all();
+
+        if let Some(n) = cfg.runtime.worker_threads {
+            builder.worker_threads(n);
+        }
+        if let Some(n) = cfg.runtime.max_blocking_threads {
+            builder.max_blocking_threads(n);
+        }
+
+        builder
+            .build()
+            .wrap_err("failed to build async runtime! check `runtime` config values")?
+    };
+
+    runtime.block_on(run(cfg))
+}
+
+async fn run(cfg: config::Config) -> eyre::Result<()> {
     // Set up tracing
     tracing_subscriber::fmt()
         .with_max_level(cfg.logger.level)
@@ -78,6 +248,90 @@ async fn main() -> eyre::Result<()> {
         if !save_path.exists() || !save_path.is_dir() {
             bail!("the save path does not exist or is not a directory! this is invalid");
         }
+
+        if let Some(ref temp_path) = cfg.disk.temp_path
+            && !temp_path.is_dir()
+        {
+            bail!("the temp path does not exist or is not a directory! this is invalid");
+        }
+
+        if cfg.disk.direct_io && !cfg!(all(feature = "direct-io", target_os = "linux")) {
+            bail!(
+                "`disk.direct_io` needs breeze to be built with the `direct-io` feature, on linux"
+            );
+        }
+    }
+    {
+        let routes = &cfg.http.routes;
+        for route in [
+            &routes.new,
+            &routes.view,
+            &routes.delete,
+            &routes.static_files,
+        ] {
+            if !route.starts_with('/') || route.ends_with('/') {
+                bail!("`http.routes` paths must start with a `/`, and not end with one");
+            }
+        }
+
+        if cfg.http.max_connections == Some(0) {
+            bail!("`http.max_connections` must be above 0");
+        }
+
+        if let Some(ref server) = cfg.http.server_header
+            && HeaderValue::from_str(server).is_err()
+        {
+            bail!("`http.server_header` isn't a valid header value");
+        }
+
+        if let Some(ref static_dir) = cfg.http.static_dir {
+            if !static_dir.is_dir() {
+                bail!("the static dir does not exist or is not a directory! this is invalid");
+            }
+
+            // static files can't be allowed to shadow anything real
+            let prefix = &routes.static_files;
+            let overlaps = |route: &str| {
+                route == prefix
+                    || route.starts_with(&format!("{prefix}/"))
+                    || prefix.starts_with(&format!("{route}/"))
+            };
+            let reserved = [
+                routes.new.as_str(),
+                routes.view.as_str(),
+                routes.delete.as_str(),
+                "/paste",
+                "/admin",
+                "/stats",
+                "/limits",
+                "/readyz",
+                "/lifetime",
+                "/favicon.ico",
+                "/robots.txt",
+            ];
+            if reserved.into_iter().any(overlaps) {
+                bail!("`http.routes.static_files` can't overlap with any other route");
+            }
+        }
+    }
+    {
+        // the cache checks this itself too, but it's nicer to hear about it here
+        if cfg.cache.mem_capacity < cfg.cache.max_length {
+            bail!("`cache.max_length` should not exceed `cache.mem_capacity`");
+        }
+
+        if let (Some(min), Some(max)) = (cfg.engine.min_upload_len, cfg.engine.max_upload_len)
+            && min > max
+        {
+            bail!("`engine.min_upload_len` should not exceed `engine.max_upload_len`");
+        }
+
+        // uploads are only stripped while they're being cached
+        if cfg.engine.max_strip_len > cfg.cache.max_length as u64 {
+            warn!(
+                "`engine.max_strip_len` is bigger than `cache.max_length`! uploads too big to cache won't have their exif data stripped"
+            );
+        }
     }
     if cfg.engine.upload_key.is_empty() {
         warn!("engine upload_key is empty! no key will be required for uploading new files");
@@ -94,20 +348,41 @@ async fn main() -> eyre::Result<()> {
     });
 
     // Create engine
-    let engine = Engine::new(cfg.engine, cache, disk)?;
+    let engine = Arc::new(Engine::new(cfg.engine, &cfg.http, cache, disk)?);
+
+    // Load favicon
+    let favicon = cfg
+        .http
+        .favicon
+        .as_deref()
+        .map(Favicon::load)
+        .transpose()
+        .wrap_err("failed to read favicon! make sure it exists and you have read permissions")?;
 
     // Build main router
-    let app = router(engine);
+    let app = router(engine.clone(), &cfg.http, favicon);
+
+    // There is nothing to warm up yet, so we're ready right away
+    engine.ready.store(true, Ordering::Relaxed);
 
     // Start web server
     info!("starting server.");
     let listener = TcpListener::bind(&cfg.http.listen_on)
         .await
         .wrap_err("failed to bind to given `http.listen_on` address! make sure it's valid, and the port isn't already bound")?;
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await
-        .wrap_err("failed to start server")?;
+    let header_read_timeout =
+        Some(cfg.http.header_read_timeout).filter(|timeout| !timeout.is_zero());
+    serve::serve(
+        listener,
+        app,
+        header_read_timeout,
+        cfg.http.max_connections,
+        shutdown_signal(),
+    )
+    .await;
+
+    // uploads that were just received might still be getting written
+    engine.wait_for_writes().await;
 
     Ok(())
 }
@@ -0,0 +1,101 @@
+use std::path::Path;
+
+/// Guess the content type of an audio or video upload from its extension.
+///
+/// Browsers can usually work out what other uploads are without a
+/// `Content-Type`, but `<video>` and `<audio>` playback (and seeking)
+/// is unreliable without one.
+pub fn media_type_for(saved_name: &str) -> Option<&'static str> {
+    let ext = Path::new(saved_name).extension()?.to_str()?;
+
+    let content_type = match ext.to_ascii_lowercase().as_str() {
+        // video
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mov" => "video/quicktime",
+        "mkv" => "video/x-matroska",
+        "ogv" => "video/ogg",
+
+        // audio
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "aac" => "audio/aac",
+        "ogg" | "oga" => "audio/ogg",
+        "opus" => "audio/opus",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+
+        _ => return None,
+    };
+
+    Some(content_type)
+}
+
+/// Guess the extension an upload should have from its content type,
+/// for uploads that weren't sent with a file name.
+pub fn extension_for(content_type: &str) -> Option<&'static str> {
+    // ignore parameters like `; charset=utf-8`
+    let essence = content_type.split(';').next()?.trim();
+
+    let ext = match essence.to_ascii_lowercase().as_str() {
+        // images
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/avif" => "avif",
+        "image/bmp" => "bmp",
+        "image/tiff" => "tiff",
+        "image/svg+xml" => "svg",
+
+        // video
+        "video/mp4" => "mp4",
+        "video/webm" => "webm",
+        "video/quicktime" => "mov",
+        "video/x-matroska" => "mkv",
+        "video/ogg" => "ogv",
+
+        // audio
+        "audio/mpeg" => "mp3",
+        "audio/mp4" => "m4a",
+        "audio/aac" => "aac",
+        "audio/ogg" => "ogg",
+        "audio/opus" => "opus",
+        "audio/wav" | "audio/x-wav" => "wav",
+        "audio/flac" => "flac",
+
+        // text and documents
+        "text/plain" => "txt",
+        "text/html" => "html",
+        "text/css" => "css",
+        "text/csv" => "csv",
+        "text/markdown" => "md",
+        "application/json" => "json",
+        "application/pdf" => "pdf",
+        "application/zip" => "zip",
+
+        _ => return None,
+    };
+
+    Some(ext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extension_for, media_type_for};
+
+    /// Make sure content types are mapped to extensions,
+    /// and that they map back for media
+    #[test]
+    fn extensions() {
+        assert_eq!(extension_for("image/png"), Some("png"));
+        assert_eq!(extension_for("Text/Plain; charset=utf-8"), Some("txt"));
+        assert_eq!(extension_for("application/octet-stream"), None);
+
+        assert_eq!(media_type_for("upload.webm"), Some("video/webm"));
+        assert_eq!(
+            extension_for(media_type_for("upload.webm").unwrap()),
+            Some("webm")
+        );
+    }
+}
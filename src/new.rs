@@ -1,23 +1,33 @@
 use std::{
     ffi::OsStr,
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
     sync::Arc,
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use axum::{
     body::Body,
-    extract::{Query, State},
-    response::{IntoResponse as _, Response},
+    extract::{ConnectInfo, Path as AxumPath, Query, State},
+    response::{IntoResponse, Response},
 };
 use axum_extra::TypedHeader;
+use base64::{Engine as _, prelude::BASE64_STANDARD};
+use color_eyre::eyre;
 use headers::ContentLength;
-use http::{HeaderValue, StatusCode};
+use http::{HeaderMap, HeaderValue, StatusCode, header};
 use serde::Deserialize;
 use serde_with::{DurationSeconds, serde_as};
 use tracing::error;
 
-use crate::engine::{Engine, ProcessOutcome};
+use crate::{
+    admin::check_key,
+    delete::{check_token, token_from_header},
+    disk,
+    engine::{Engine, ProcessOutcome, UploadEncoding, UploadOptions},
+    mime,
+    view::parse_rfc3339,
+};
 
 fn default_keep_exif() -> bool {
     false
@@ -26,6 +36,7 @@ fn default_keep_exif() -> bool {
 #[serde_as]
 #[derive(Deserialize)]
 pub struct NewRequest {
+    #[serde(default)]
     name: String,
     key: Option<String>,
 
@@ -33,35 +44,400 @@ pub struct NewRequest {
     #[serde_as(as = "Option<DurationSeconds>")]
     last_for: Option<Duration>,
 
+    /// When a temporary upload should expire, instead of `lastfor`
+    /// (RFC 3339 or a unix timestamp)
+    expires_at: Option<String>,
+
+    #[serde(rename = "cachefor", alias = "cache_for")]
+    #[serde_as(as = "Option<DurationSeconds>")]
+    cache_for: Option<Duration>,
+
     #[serde(rename = "keepexif", default = "default_keep_exif")]
     keep_exif: bool,
+
+    sha256: Option<String>,
+
+    validate: Option<String>,
+
+    /// Skip the cache, and only save the upload to disk
+    nocache: Option<String>,
+
+    /// Don't give out a deletion URL for the upload
+    nodelete: Option<String>,
+
+    /// Saved name of an upload to overwrite, instead of making a new one.
+    /// Needs its deletion token (`hash` and `hmac`) or the admin key
+    replace: Option<String>,
+    hash: Option<String>,
+    hmac: Option<String>,
+    admin_key: Option<String>,
+}
+
+impl NewRequest {
+    /// Whether the upload should only be checked, not saved
+    fn is_validate(&self) -> bool {
+        self.validate
+            .as_deref()
+            .is_some_and(|v| v == "1" || v == "true")
+    }
+
+    /// Whether the upload should skip the cache
+    fn is_no_cache(&self) -> bool {
+        self.nocache
+            .as_deref()
+            .is_some_and(|n| n == "1" || n == "true")
+    }
+
+    /// Whether the upload shouldn't get a deletion URL
+    fn is_no_delete(&self) -> bool {
+        self.nodelete
+            .as_deref()
+            .is_some_and(|n| n == "1" || n == "true")
+    }
+}
+
+/// Work out how the upload body was encoded from its `Content-Encoding` header.
+///
+/// Returns: `None` if it was encoded in a way we can't decode
+fn upload_encoding(headers: &HeaderMap) -> Option<UploadEncoding> {
+    let Some(value) = headers.get(header::CONTENT_ENCODING) else {
+        return Some(UploadEncoding::Identity);
+    };
+
+    match value.to_str().ok()?.trim().to_ascii_lowercase().as_str() {
+        "" | "identity" => Some(UploadEncoding::Identity),
+        "gzip" | "x-gzip" => Some(UploadEncoding::Gzip),
+        "deflate" => Some(UploadEncoding::Deflate),
+        _ => None,
+    }
+}
+
+/// Parse when an upload should expire, as an RFC 3339 or unix timestamp
+pub fn parse_expiry(at: &str) -> Option<SystemTime> {
+    if at.bytes().all(|b| b.is_ascii_digit()) {
+        // far enough in the future, this can't be represented
+        return UNIX_EPOCH.checked_add(Duration::from_secs(at.parse().ok()?));
+    }
+
+    parse_rfc3339(at)
+}
+
+/// Decode a hex-encoded SHA-256 digest
+fn decode_hex_sha256(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut digest = [0; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(digest)
+}
+
+/// Find the SHA-256 digest a client wants its upload checked against.
+/// It can be given in the `sha256` query parameter (hex), or
+/// as a `Digest: sha-256=...` header (base64).
+///
+/// Returns: `Err` if a digest was given but it is invalid
+fn expected_sha256(query: Option<&str>, headers: &HeaderMap) -> Result<Option<[u8; 32]>, ()> {
+    if let Some(hex) = query {
+        return decode_hex_sha256(hex).map(Some).ok_or(());
+    }
+
+    let Some(header) = headers.get("Digest") else {
+        return Ok(None);
+    };
+    let header = header.to_str().map_err(|_| ())?;
+
+    // there can be several digests listed, we only know sha-256
+    let Some(b64) = header.split(',').find_map(|d| {
+        let (alg, value) = d.trim().split_once('=')?;
+        alg.eq_ignore_ascii_case("sha-256").then_some(value)
+    }) else {
+        return Ok(None);
+    };
+
+    BASE64_STANDARD
+        .decode(b64)
+        .ok()
+        .and_then(|v| v.try_into().ok())
+        .map(Some)
+        .ok_or(())
+}
+
+/// Lowercase a (possibly combined, like `tar.gz`) extension
+/// and replace common aliases with one canonical form.
+fn normalize_extension(ext: &str) -> String {
+    ext.split('.')
+        .map(|part| match part.to_ascii_lowercase().as_str() {
+            "jpeg" | "jpe" => "jpg".to_string(),
+            "tif" => "tiff".to_string(),
+            "htm" => "html".to_string(),
+            "yml" => "yaml".to_string(),
+            other => other.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Make sure the upload key given is correct, if one is needed.
+///
+/// If `engine.verbose_auth_errors` is enabled, the rejection says
+/// whether the key was missing or wrong. Otherwise, it's just a 403.
+pub fn check_upload_key(
+    engine: &Engine,
+    key: Option<&str>,
+) -> Result<(), (StatusCode, &'static str)> {
+    let upload_key = &engine.cfg.upload_key;
+    if upload_key.is_empty() {
+        return Ok(());
+    }
+
+    let msg = match key {
+        Some(key) if key == upload_key => return Ok(()),
+        Some(_) => "Upload key was rejected",
+        None => "No upload key was provided",
+    };
+
+    if engine.cfg.verbose_auth_errors {
+        Err((StatusCode::FORBIDDEN, msg))
+    } else {
+        Err((StatusCode::FORBIDDEN, ""))
+    }
+}
+
+/// Whether a name given by the client is a plain saved name,
+/// so nothing outside the save path (or hidden in it) can be touched
+fn is_plain_name(saved_name: &str) -> bool {
+    Path::new(saved_name).file_name() == Some(OsStr::new(saved_name)) && disk::is_upload(saved_name)
+}
+
+/// Make sure the client is allowed to replace an upload.
+/// This needs its deletion token, or the admin key.
+async fn check_replace(
+    engine: &Engine,
+    saved_name: &str,
+    req: &NewRequest,
+) -> Result<(), (StatusCode, &'static str)> {
+    if !is_plain_name(saved_name) {
+        return Err((StatusCode::BAD_REQUEST, "Upload name is invalid"));
+    }
+
+    if req.last_for.is_some() || req.expires_at.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Temporary uploads can't replace others",
+        ));
+    }
+
+    if req.admin_key.is_some() {
+        return check_key(engine, req.admin_key.clone());
+    }
+
+    match (&req.hash, &req.hmac) {
+        (Some(hash), Some(hmac)) if engine.deletion_hmac.is_some() => {
+            check_token(engine, saved_name, hash, hmac).await
+        }
+        _ => Err((
+            StatusCode::FORBIDDEN,
+            "Replacing an upload needs its deletion token or the admin key",
+        )),
+    }
+}
+
+/// Work out how an upload should be handled from its request
+fn upload_options(
+    req: &NewRequest,
+    headers: &HeaderMap,
+    ip: IpAddr,
+) -> Result<UploadOptions, (StatusCode, &'static str)> {
+    // the client might want the upload checked against a digest
+    let Ok(sha256) = expected_sha256(req.sha256.as_deref(), headers) else {
+        return Err((StatusCode::BAD_REQUEST, "Digest is invalid"));
+    };
+
+    // compressed uploads are decoded before they're saved
+    let Some(encoding) = upload_encoding(headers) else {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content encoding is not supported",
+        ));
+    };
+
+    // an expiry time can be given instead of a lifetime.
+    // if both are, the lifetime wins
+    let lifetime = match (req.last_for, req.expires_at.as_deref()) {
+        (None, Some(at)) => {
+            let Some(at) = parse_expiry(at) else {
+                return Err((StatusCode::BAD_REQUEST, "Expiry time is invalid"));
+            };
+            let Ok(lifetime) = at.duration_since(SystemTime::now()) else {
+                return Err((StatusCode::BAD_REQUEST, "Expiry time has already passed"));
+            };
+            Some(lifetime)
+        }
+        (last_for, _) => last_for,
+    };
+
+    Ok(UploadOptions {
+        lifetime,
+        cache_lifetime: req.cache_for,
+        keep_exif: req.keep_exif,
+        sha256,
+        encoding,
+        client_ip: Some(ip),
+        replace: req.replace.is_some(),
+        no_cache: req.is_no_cache(),
+        no_delete: req.is_no_delete(),
+    })
+}
+
+/// Turn what happened to an upload into the response for it
+fn outcome_response(res: eyre::Result<ProcessOutcome>) -> Response {
+    match res {
+        Ok(outcome) => match outcome {
+            // 200 OK
+            ProcessOutcome::Success {
+                url,
+                deletion_url,
+                stored_len,
+                content_type,
+            } => {
+                let mut res = url.into_response();
+                let headers = res.headers_mut();
+
+                // insert deletion url header if needed
+                if let Some(deletion_url) = deletion_url {
+                    let deletion_url = HeaderValue::from_str(&deletion_url)
+                        .expect("deletion url contains invalid chars");
+
+                    headers.insert("Breeze-Deletion-Url", deletion_url);
+                }
+
+                // let the client check what was actually stored
+                headers.insert("X-Stored-Size", HeaderValue::from(stored_len));
+                if let Some(content_type) = content_type {
+                    headers.insert("X-Content-Type", HeaderValue::from_static(content_type));
+                }
+
+                res
+            }
+
+            // 413 Payload Too Large
+            ProcessOutcome::UploadTooLarge | ProcessOutcome::TemporaryUploadTooLarge => {
+                StatusCode::PAYLOAD_TOO_LARGE.into_response()
+            }
+
+            // 409 Conflict
+            ProcessOutcome::AlreadyInProgress => StatusCode::CONFLICT.into_response(),
+
+            // 404 Not Found
+            ProcessOutcome::NotFound => {
+                (StatusCode::NOT_FOUND, "Upload to replace was not found").into_response()
+            }
+
+            // 422 Unprocessable Entity
+            ProcessOutcome::DigestMismatch => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "Digest did not match").into_response()
+            }
+            ProcessOutcome::ExifStripFailed => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Could not remove EXIF data",
+            )
+                .into_response(),
+
+            // 503 Service Unavailable
+            ProcessOutcome::Cancelled => {
+                (StatusCode::SERVICE_UNAVAILABLE, "Upload was cancelled").into_response()
+            }
+
+            // 507 Insufficient Storage
+            ProcessOutcome::TooManyFiles => StatusCode::INSUFFICIENT_STORAGE.into_response(),
+            ProcessOutcome::StorageUnavailable => (
+                StatusCode::INSUFFICIENT_STORAGE,
+                "Upload storage is unavailable",
+            )
+                .into_response(),
+
+            // 400 Bad Request
+            ProcessOutcome::UploadTooSmall | ProcessOutcome::TemporaryUploadLifetimeTooLong => {
+                StatusCode::BAD_REQUEST.into_response()
+            }
+        },
+
+        // 500 Internal Server Error
+        Err(err) => {
+            error!("failed to process upload!! {err:#}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// OPTIONS request handler for the /new path.
+/// Lets clients know the max upload length without uploading anything.
+pub async fn options(State(engine): State<Arc<Engine>>) -> Response {
+    let mut res = StatusCode::NO_CONTENT.into_response();
+    let headers = res.headers_mut();
+
+    headers.insert(header::ALLOW, HeaderValue::from_static("POST, OPTIONS"));
+    if let Some(max_upload_len) = engine.cfg.max_upload_len {
+        headers.insert("X-Max-Upload-Len", HeaderValue::from(max_upload_len));
+    }
+
+    res
 }
 
 /// The request handler for the /new path.
 /// This handles all new uploads.
 pub async fn new(
     State(engine): State<Arc<Engine>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Query(req): Query<NewRequest>,
     TypedHeader(ContentLength(content_length)): TypedHeader<ContentLength>,
+    headers: HeaderMap,
     body: Body,
-) -> Result<Response, StatusCode> {
+) -> Result<Response, Response> {
+    let validate = req.is_validate();
+
     // check upload key, if i need to
-    if !engine.cfg.upload_key.is_empty() && req.key.unwrap_or_default() != engine.cfg.upload_key {
-        return Err(StatusCode::FORBIDDEN);
-    }
+    check_upload_key(&engine, req.key.as_deref()).map_err(IntoResponse::into_response)?;
 
-    // the original file name wasn't given, so i can't work out what the extension should be
-    if req.name.is_empty() {
-        return Err(StatusCode::BAD_REQUEST);
+    // overwriting an upload needs proof the client is allowed to
+    if let Some(ref saved_name) = req.replace {
+        check_replace(&engine, saved_name, &req)
+            .await
+            .map_err(IntoResponse::into_response)?;
     }
 
+    let opts = upload_options(&req, &headers, addr.ip()).map_err(IntoResponse::into_response)?;
+
+    // the original file name wasn't given (clipboard tools often don't send one),
+    // so try to work out the extension from the content type instead.
+    // replacements keep the name they already have
+    let name = if req.name.is_empty() && req.replace.is_none() {
+        let Some(ext) = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(mime::extension_for)
+        else {
+            // can't work out what the extension should be
+            return Err(StatusCode::BAD_REQUEST.into_response());
+        };
+
+        format!("upload.{ext}")
+    } else {
+        req.name
+    };
+
     // -- try to figure out a file extension..
 
     fn extension(pb: &Path) -> Option<String> {
         pb.extension().and_then(OsStr::to_str).map(str::to_string)
     }
 
-    let pb = PathBuf::from(req.name);
+    let pb = PathBuf::from(name);
     let mut ext = extension(&pb);
 
     // common extensions that usually have a second extension before themselves
@@ -70,7 +446,7 @@ pub async fn new(
     // if the extension is one of those, try to find that second extension
     if ext
         .as_ref()
-        .is_some_and(|ext| ADDITIVE.contains(&ext.as_str()))
+        .is_some_and(|ext| ADDITIVE.contains(&ext.to_ascii_lowercase().as_str()))
     {
         // try to parse out another extension
         let stem = pb.file_stem().unwrap(); // SAFETY: if extension is Some(), this will also be
@@ -87,48 +463,206 @@ pub async fn new(
         }
     }
 
-    // turn body into stream
+    // tidy up the extension if we're supposed to
+    if engine.cfg.normalize_extensions {
+        ext = ext.as_deref().map(normalize_extension);
+    }
+
+    let res = if validate {
+        // the client only wants to know if the upload would be accepted,
+        // so run the checks without ever reading the body
+        let Some(rejection) = engine.precheck(content_length, &opts) else {
+            return Ok((StatusCode::OK, "Upload would be accepted").into_response());
+        };
+
+        Ok(rejection)
+    } else {
+        // turn body into stream
+        let stream = Body::into_data_stream(body);
+
+        // pass it off to the engine to be processed
+        // --
+        // also, error responses here don't get presented properly in ShareX most of the time
+        // they don't expect the connection to close before they're done uploading, i think
+        // so it will just present the user with a "connection closed" error
+        match req.replace {
+            Some(ref saved_name) => {
+                engine
+                    .replace(saved_name, content_length, stream, opts)
+                    .await
+            }
+            None => engine.process(ext, content_length, stream, opts).await,
+        }
+    };
+
+    Ok(outcome_response(res))
+}
+
+/// PUT request handler for the view path, if `http.webdav` is enabled.
+///
+/// This stores the upload under exactly the name in the path.
+/// If that name is already taken, the upload replaces it, which needs
+/// its deletion token (in the `Breeze-Deletion-Token` header) or the admin key.
+pub async fn put(
+    State(engine): State<Arc<Engine>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    AxumPath(saved_name): AxumPath<String>,
+    Query(mut req): Query<NewRequest>,
+    TypedHeader(ContentLength(content_length)): TypedHeader<ContentLength>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, Response> {
+    check_upload_key(&engine, req.key.as_deref()).map_err(IntoResponse::into_response)?;
+
+    if !is_plain_name(&saved_name) {
+        return Err((StatusCode::BAD_REQUEST, "Upload name is invalid").into_response());
+    }
+
+    let exists = engine.has(&saved_name).await;
+    if exists {
+        if let Some((hash, hmac)) = token_from_header(&headers) {
+            req.hash = Some(hash);
+            req.hmac = Some(hmac);
+        }
+
+        check_replace(&engine, &saved_name, &req)
+            .await
+            .map_err(IntoResponse::into_response)?;
+    }
+
+    // the name in the path decides what gets replaced, not the query.
+    // otherwise a new upload could pass itself off as a replacement,
+    // and skip the checks for adding one
+    req.replace = exists.then(|| saved_name.clone());
+
+    let opts = upload_options(&req, &headers, addr.ip()).map_err(IntoResponse::into_response)?;
     let stream = Body::into_data_stream(body);
 
-    // pass it off to the engine to be processed
-    // --
-    // also, error responses here don't get presented properly in ShareX most of the time
-    // they don't expect the connection to close before they're done uploading, i think
-    // so it will just present the user with a "connection closed" error
-    match engine
-        .process(ext, content_length, stream, req.last_for, req.keep_exif)
-        .await
-    {
-        Ok(outcome) => match outcome {
-            // 200 OK
-            ProcessOutcome::Success { url, deletion_url } => {
-                let mut res = url.into_response();
+    let res = if exists {
+        engine
+            .replace(&saved_name, content_length, stream, opts)
+            .await
+    } else {
+        engine
+            .process_named(&saved_name, content_length, stream, opts)
+            .await
+    };
 
-                // insert deletion url header if needed
-                if let Some(deletion_url) = deletion_url {
-                    let deletion_url = HeaderValue::from_str(&deletion_url)
-                        .expect("deletion url contains invalid chars");
+    Ok(outcome_response(res))
+}
 
-                    let headers = res.headers_mut();
-                    headers.insert("Breeze-Deletion-Url", deletion_url);
-                }
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
 
-                Ok(res)
-            }
+    use axum::{
+        body::Body,
+        extract::{ConnectInfo, Path as AxumPath, Query, State},
+    };
+    use axum_extra::TypedHeader;
+    use headers::ContentLength;
+    use http::{HeaderMap, StatusCode};
 
-            // 413 Payload Too Large
-            ProcessOutcome::UploadTooLarge | ProcessOutcome::TemporaryUploadTooLarge => {
-                Err(StatusCode::PAYLOAD_TOO_LARGE)
-            }
+    use super::{NewRequest, decode_hex_sha256, normalize_extension, parse_expiry, put};
+    use crate::engine::{
+        Engine,
+        tests::{test_dir, test_engine_with},
+    };
 
-            // 400 Bad Request
-            ProcessOutcome::TemporaryUploadLifetimeTooLong => Err(StatusCode::BAD_REQUEST),
-        },
+    /// Make sure extensions are lowercased
+    /// and aliases are replaced
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_extension("png"), "png");
+        assert_eq!(normalize_extension("PNG"), "png");
+        assert_eq!(normalize_extension("JPEG"), "jpg");
+        assert_eq!(normalize_extension("tif"), "tiff");
 
-        // 500 Internal Server Error
-        Err(err) => {
-            error!("failed to process upload!! {err:#}");
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        // combined extensions keep working
+        assert_eq!(normalize_extension("TAR.GZ"), "tar.gz");
+        assert_eq!(normalize_extension("yml.zst"), "yaml.zst");
+    }
+
+    /// Make sure hex digests are decoded,
+    /// and bad ones are rejected
+    #[test]
+    fn decode_sha256() {
+        let hex = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let digest = decode_hex_sha256(hex).unwrap();
+        assert_eq!(digest[0], 0xe3);
+        assert_eq!(digest[31], 0x55);
+
+        // wrong length, not hex
+        assert!(decode_hex_sha256(&hex[2..]).is_none());
+        assert!(decode_hex_sha256(&hex.replace('e', "z")).is_none());
+    }
+
+    /// Make sure expiry times are parsed,
+    /// and ones too big to represent are rejected instead of panicking
+    #[test]
+    fn expiry_times() {
+        assert_eq!(
+            parse_expiry("1700000000"),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+        assert_eq!(
+            parse_expiry("2023-11-14T22:13:20Z"),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+
+        assert!(parse_expiry("9999999999999999999").is_none());
+        assert!(parse_expiry("99999999999999999999999").is_none());
+        assert!(parse_expiry("soon").is_none());
+    }
+
+    /// PUT an upload through the handler, returning the response status
+    async fn put_status(
+        engine: &Arc<Engine>,
+        saved_name: &str,
+        query: &str,
+        data: &'static [u8],
+    ) -> StatusCode {
+        let uri = format!("http://localhost/{saved_name}?{query}")
+            .parse()
+            .unwrap();
+        let res = put(
+            State(engine.clone()),
+            ConnectInfo("127.0.0.1:1234".parse::<SocketAddr>().unwrap()),
+            AxumPath(saved_name.to_string()),
+            Query::<NewRequest>::try_from_uri(&uri).unwrap(),
+            TypedHeader(ContentLength(data.len() as u64)),
+            HeaderMap::new(),
+            Body::from(data),
+        )
+        .await;
+
+        match res {
+            Ok(res) | Err(res) => res.status(),
         }
     }
+
+    /// Make sure a PUT to a new name can't claim to be replacing
+    /// another upload, and get around `max_files` that way
+    #[tokio::test]
+    async fn put_new_name_ignores_replace() {
+        let engine = Arc::new(test_engine_with(
+            "put_new_name_ignores_replace",
+            "",
+            "max_files = 1",
+        ));
+
+        let status = put_status(&engine, "first.txt", "", b"first").await;
+        assert_eq!(status, StatusCode::OK);
+
+        // the disk is full now, whatever the query says
+        let status = put_status(&engine, "second.txt", "replace=first.txt", b"second").await;
+        assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE);
+        assert!(!engine.has("second.txt").await);
+
+        std::fs::remove_dir_all(test_dir("put_new_name_ignores_replace")).unwrap();
+    }
 }
@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use http::{HeaderValue, StatusCode};
+use serde::Deserialize;
+use tracing::error;
+
+use crate::{
+    engine::{AppendOutcome, CreatePasteOutcome, Engine},
+    feature::Feature,
+    new::check_upload_key,
+};
+
+#[derive(Deserialize)]
+pub struct CreateRequest {
+    key: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AppendRequest {
+    token: String,
+}
+
+/// The request handler for POST /paste.
+/// This creates a new, empty paste that can be appended to.
+///
+/// The paste's URL is sent back in the body,
+/// and the URL to append to it with is sent in the `Breeze-Append-Url` header.
+pub async fn create(
+    State(engine): State<Arc<Engine>>,
+    Query(req): Query<CreateRequest>,
+) -> Result<Response, (StatusCode, &'static str)> {
+    if engine.deletion_hmac.is_none() {
+        return Err(Feature::Paste.disabled());
+    }
+
+    // check upload key, if i need to
+    check_upload_key(&engine, req.key.as_deref())?;
+
+    match engine.create_paste().await {
+        Ok(CreatePasteOutcome::Success { url, append_url }) => {
+            let mut res = url.into_response();
+
+            let append_url =
+                HeaderValue::from_str(&append_url).expect("append url contains invalid chars");
+            res.headers_mut().insert("Breeze-Append-Url", append_url);
+
+            Ok(res)
+        }
+        Ok(CreatePasteOutcome::TooManyFiles) => {
+            Err((StatusCode::INSUFFICIENT_STORAGE, "Too many files"))
+        }
+        Err(err) => {
+            error!("failed to create paste!! {err:#}");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Internal server error!"))
+        }
+    }
+}
+
+/// The request handler for PATCH /paste/{saved_name}.
+/// The request body is appended to the end of the paste.
+pub async fn append(
+    State(engine): State<Arc<Engine>>,
+    Path(saved_name): Path<String>,
+    Query(req): Query<AppendRequest>,
+    body: Bytes,
+) -> (StatusCode, &'static str) {
+    if engine.deletion_hmac.is_none() {
+        return Feature::Paste.disabled();
+    }
+
+    match engine.append(&saved_name, &req.token, body).await {
+        Ok(AppendOutcome::Success) => (StatusCode::OK, "Appended"),
+        Ok(AppendOutcome::NotFound) => (StatusCode::NOT_FOUND, "Not found!"),
+        Ok(AppendOutcome::InvalidToken) => (StatusCode::FORBIDDEN, "Token is invalid"),
+        Ok(AppendOutcome::UploadTooLarge) => (StatusCode::PAYLOAD_TOO_LARGE, "Paste is too large"),
+        Err(err) => {
+            error!("failed to append to paste!! {err:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error!")
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use tokio::time::Instant;
+
+/// How many clients can be tracked before
+/// expired windows are swept out
+const SWEEP_THRESHOLD: usize = 4096;
+
+/// A simple fixed-window rate limiter, keyed by client IP.
+pub struct RateLimiter {
+    /// Hits allowed per window
+    max: u32,
+
+    /// How long a window lasts
+    window: Duration,
+
+    /// When each client's current window started, and how many hits it has had
+    hits: DashMap<IpAddr, (Instant, u32)>,
+}
+
+impl RateLimiter {
+    pub fn new(max: u32, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            hits: DashMap::new(),
+        }
+    }
+
+    /// Record a hit from a client.
+    ///
+    /// Returns: `true` if the client is within its limit, `false` if it is over
+    pub fn hit(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+
+        // Don't let clients that stopped sending requests pile up forever
+        if self.hits.len() >= SWEEP_THRESHOLD {
+            self.hits
+                .retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        }
+
+        let mut e = self.hits.entry(ip).or_insert((now, 0));
+        let (start, count) = e.value_mut();
+
+        // Start a new window if the last one is over
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+
+        *count = count.saturating_add(1);
+
+        *count <= self.max
+    }
+
+    /// Check if a client is over its limit, without recording a hit.
+    pub fn is_limited(&self, ip: IpAddr) -> bool {
+        self.hits.get(&ip).is_some_and(|e| {
+            let (start, count) = *e;
+            Instant::now().duration_since(start) < self.window && count >= self.max
+        })
+    }
+}
+
+/// Limits how many things a single client can have going on at once, keyed by client IP.
+pub struct ConcurrencyLimiter {
+    /// Permits each client can hold at once
+    max: usize,
+
+    /// How many permits each client currently holds.
+    /// Clients are removed once they hold none
+    active: Arc<DashMap<IpAddr, usize>>,
+}
+
+/// Held by a client while it is doing something.
+/// Its slot is given back when this is dropped.
+pub struct ConcurrencyPermit {
+    ip: IpAddr,
+    active: Arc<DashMap<IpAddr, usize>>,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            active: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Try to take a permit for a client.
+    ///
+    /// Returns: the permit, or `None` if the client already holds as many as it can
+    pub fn acquire(&self, ip: IpAddr) -> Option<ConcurrencyPermit> {
+        let mut e = self.active.entry(ip).or_insert(0);
+        if *e >= self.max {
+            return None;
+        }
+        *e += 1;
+
+        Some(ConcurrencyPermit {
+            ip,
+            active: self.active.clone(),
+        })
+    }
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        if let Some(mut e) = self.active.get_mut(&self.ip) {
+            *e = e.saturating_sub(1);
+        }
+
+        // forget about clients that aren't doing anything
+        self.active.remove_if(&self.ip, |_, n| *n == 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::Duration,
+    };
+
+    use super::{ConcurrencyLimiter, RateLimiter};
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const OTHER_IP: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+    /// Make sure clients are limited once they
+    /// go over, and let back in after the window
+    #[tokio::test(start_paused = true)]
+    async fn limit_and_reset() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+
+        // within limit
+        assert!(limiter.hit(IP));
+        assert!(!limiter.is_limited(IP));
+        assert!(limiter.hit(IP));
+        assert!(limiter.is_limited(IP));
+
+        // over limit
+        assert!(!limiter.hit(IP));
+
+        // other clients aren't affected
+        assert!(limiter.hit(OTHER_IP));
+
+        // next window
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(!limiter.is_limited(IP));
+        assert!(limiter.hit(IP));
+    }
+
+    /// Make sure clients can't hold more permits than allowed,
+    /// and get them back once they're dropped
+    #[test]
+    fn concurrency_permits() {
+        let limiter = ConcurrencyLimiter::new(2);
+
+        let first = limiter.acquire(IP).unwrap();
+        let second = limiter.acquire(IP).unwrap();
+        assert!(limiter.acquire(IP).is_none());
+
+        // other clients aren't affected
+        let other = limiter.acquire(OTHER_IP).unwrap();
+
+        // dropping a permit frees a slot
+        drop(first);
+        let third = limiter.acquire(IP).unwrap();
+        assert!(limiter.acquire(IP).is_none());
+
+        // idle clients aren't kept around
+        drop((second, third, other));
+        assert!(limiter.active.is_empty());
+    }
+}
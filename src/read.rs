@@ -0,0 +1,27 @@
+use std::io::{self, Read};
+
+use bytes::Bytes;
+use tokio::fs::File;
+
+/// Read a whole upload from disk into memory, given how long it should be.
+///
+/// This is done in one go on a blocking thread. Reading through tokio's
+/// [`File`] instead hands each chunk (of at most 2MiB) off to a blocking
+/// thread by itself, which adds up for big uploads.
+///
+/// The file could have changed since its length was taken,
+/// so it's still read to the end instead of trusting `len`.
+pub async fn read_whole(f: File, len: u64) -> io::Result<Bytes> {
+    let len: usize = len.try_into().map_err(io::Error::other)?;
+    let mut f = f.into_std().await;
+
+    tokio::task::spawn_blocking(move || {
+        // sized up front, so the whole upload is one allocation
+        let mut data = Vec::with_capacity(len);
+        f.read_to_end(&mut data)?;
+
+        Ok(Bytes::from(data))
+    })
+    .await
+    .map_err(io::Error::other)?
+}
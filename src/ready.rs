@@ -0,0 +1,37 @@
+use std::sync::{Arc, atomic::Ordering};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+
+use crate::engine::Engine;
+
+/// The request handler for the /readyz path.
+/// Reports whether the server has finished starting up,
+/// and can still write uploads to its save path.
+pub async fn readyz(State(engine): State<Arc<Engine>>) -> (StatusCode, &'static str) {
+    if !engine.ready.load(Ordering::Relaxed) {
+        (StatusCode::SERVICE_UNAVAILABLE, "Not ready")
+    } else if !engine.check_storage().await {
+        (StatusCode::SERVICE_UNAVAILABLE, "Storage unavailable")
+    } else {
+        (StatusCode::OK, "Ready")
+    }
+}
+
+/// Middleware that rejects requests with 503 until the server is ready,
+/// if `engine.wait_for_ready` is enabled.
+pub async fn require_ready(
+    State(engine): State<Arc<Engine>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if engine.cfg.wait_for_ready && !engine.ready.load(Ordering::Relaxed) {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Server is starting up").into_response();
+    }
+
+    next.run(req).await
+}
@@ -0,0 +1,91 @@
+use std::{future::Future, net::SocketAddr, pin::pin, sync::Arc, time::Duration};
+
+use axum::Router;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::{conn::auto, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use tokio::{net::TcpListener, sync::Semaphore};
+use tower::Service;
+use tracing::{debug, warn};
+
+/// Serve the app until `shutdown` completes,
+/// then wait for open connections to finish.
+///
+/// This does the same as [`axum::serve`], except that hyper's
+/// timeouts can be set, so connections that are too slow
+/// to send their headers (or a new request) are closed.
+/// Like it, both HTTP/1 and HTTP/2 (without TLS) are spoken.
+/// The timeout only applies to HTTP/1, HTTP/2 has no equivalent.
+///
+/// If `max_connections` is set, no more than that many
+/// connections are accepted at once.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    header_read_timeout: Option<Duration>,
+    max_connections: Option<usize>,
+    shutdown: impl Future<Output = ()>,
+) {
+    let mut make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+    let graceful = GracefulShutdown::new();
+
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(header_read_timeout);
+
+    let slots = max_connections.map(|n| Arc::new(Semaphore::new(n)));
+
+    let mut shutdown = pin!(shutdown);
+    loop {
+        // wait for a free slot before accepting anything,
+        // so extra connections are left in the listen backlog
+        let permit = match slots {
+            Some(ref slots) => tokio::select! {
+                permit = slots.clone().acquire_owned() => {
+                    Some(permit.expect("connection semaphore was closed"))
+                }
+                () = &mut shutdown => break,
+            },
+            None => None,
+        };
+
+        let (stream, addr) = tokio::select! {
+            res = listener.accept() => match res {
+                Ok(conn) => conn,
+                Err(err) => {
+                    // this is usually running out of file descriptors,
+                    // so give some connections a chance to close
+                    warn!(%err, "failed to accept connection");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            () = &mut shutdown => break,
+        };
+
+        let tower_service = make_service
+            .call(addr)
+            .await
+            .unwrap_or_else(|err| match err {});
+        let hyper_service = TowerToHyperService::new(tower_service);
+
+        let conn = builder
+            .serve_connection(TokioIo::new(stream), hyper_service)
+            .into_owned();
+        let conn = graceful.watch(conn);
+        tokio::spawn(async move {
+            if let Err(err) = conn.await {
+                debug!(%addr, %err, "connection closed with an error");
+            }
+
+            // the slot is free once the connection is done
+            drop(permit);
+        });
+    }
+
+    graceful.shutdown().await;
+}
@@ -0,0 +1,118 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{admin::check_key, engine::Engine, view::fmt_rfc3339};
+
+#[derive(Deserialize)]
+pub struct StatsRequest {
+    key: Option<String>,
+}
+
+/// A summary of how the instance is being used
+#[derive(Serialize)]
+pub struct StatsResponse {
+    /// Number of uploads saved to disk
+    uploads: usize,
+
+    /// Number of temporary uploads
+    temp_uploads: usize,
+
+    /// Total length of uploads saved to disk (in bytes)
+    disk_bytes: u64,
+
+    /// When the oldest upload on disk was saved (RFC 3339)
+    oldest_upload: Option<String>,
+
+    /// When the newest upload on disk was saved (RFC 3339)
+    newest_upload: Option<String>,
+
+    /// Number of uploads in the cache
+    cache_entries: usize,
+
+    /// Total length of uploads in the cache (in bytes)
+    cache_bytes: usize,
+
+    /// How many times removing EXIF data has failed, by file extension
+    exif_strip_failures: BTreeMap<String, u64>,
+
+    /// Uploads processed since the server started, by file extension
+    uploads_by_extension: BTreeMap<String, ExtensionStats>,
+
+    /// Uploads processed since the server started, by size
+    upload_sizes: Vec<SizeBucket>,
+
+    /// How long the server has been running (in seconds)
+    uptime: u64,
+
+    /// Current breeze version
+    version: &'static str,
+}
+
+/// Uploads of one file extension
+#[derive(Serialize)]
+pub struct ExtensionStats {
+    /// How many there were
+    uploads: u64,
+
+    /// Their total length (in bytes)
+    bytes: u64,
+}
+
+/// Uploads that were no bigger than `le` bytes,
+/// and bigger than the previous bucket's bound
+#[derive(Serialize)]
+pub struct SizeBucket {
+    /// Upper bound of the bucket (in bytes).
+    /// This is `null` for the last bucket, which has no bound
+    le: Option<u64>,
+
+    /// How many uploads were in the bucket
+    uploads: u64,
+}
+
+/// The request handler for the /stats path.
+///
+/// This needs the admin key, unless `engine.public_stats` is enabled.
+pub async fn stats(
+    State(engine): State<Arc<Engine>>,
+    Query(req): Query<StatsRequest>,
+) -> Result<Json<StatsResponse>, (StatusCode, &'static str)> {
+    if !engine.cfg.public_stats {
+        check_key(&engine, req.key)?;
+    }
+
+    let stats = engine.stats().await.map_err(|err| {
+        error!("failed to get stats!! {err:#}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error!")
+    })?;
+
+    Ok(Json(StatsResponse {
+        uploads: stats.upl_count,
+        temp_uploads: stats.temp_count,
+        disk_bytes: stats.disk_usage.length,
+        oldest_upload: stats.disk_usage.oldest.map(fmt_rfc3339),
+        newest_upload: stats.disk_usage.newest.map(fmt_rfc3339),
+        cache_entries: stats.cache_count,
+        cache_bytes: stats.cache_length,
+        exif_strip_failures: stats.strip_failures,
+        uploads_by_extension: stats
+            .ext_uploads
+            .into_iter()
+            .map(|(ext, (uploads, bytes))| (ext, ExtensionStats { uploads, bytes }))
+            .collect(),
+        upload_sizes: stats
+            .upload_sizes
+            .into_iter()
+            .map(|(le, uploads)| SizeBucket { le, uploads })
+            .collect(),
+        uptime: stats.uptime.as_secs(),
+        version: env!("CARGO_PKG_VERSION"),
+    }))
+}
@@ -1,29 +1,110 @@
-use std::{ffi::OsStr, path::PathBuf, sync::Arc};
+use std::{
+    ffi::OsStr,
+    io::SeekFrom,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use axum::{
+    Json,
     body::Body,
-    extract::{Path, State},
+    extract::{ConnectInfo, Path, Query, State},
     response::{IntoResponse, Response},
 };
 
 use axum_extra::TypedHeader;
+use bytes::Bytes;
 use headers::Range;
-use http::{HeaderValue, StatusCode};
+use http::{HeaderMap, HeaderValue, StatusCode, header};
+use rand::distr::{Alphanumeric, SampleString};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::{OwnedSemaphorePermit, mpsc},
+};
+use tokio_stream::{StreamExt, wrappers::ReceiverStream};
 use tokio_util::io::ReaderStream;
 use tracing::error;
 
-use crate::engine::{Engine, GetOutcome, UploadData, UploadResponse};
+use crate::{
+    engine::{Engine, GetOutcome, UploadData, UploadResponse},
+    mime,
+};
+
+/// Path of a view request: the saved name, optionally followed
+/// by a friendlier file name (like `/p/abcdef.png/vacation.png`)
+#[derive(Deserialize)]
+pub struct ViewPath {
+    saved_name: PathBuf,
+
+    #[serde(default)]
+    file_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ViewRequest {
+    follow: Option<String>,
+    info: Option<String>,
+}
+
+impl ViewRequest {
+    /// Whether the client wants the upload's metadata instead of its data
+    fn is_info(&self) -> bool {
+        self.info
+            .as_deref()
+            .is_some_and(|i| i == "1" || i == "true")
+    }
+}
+
+/// Metadata about an upload, sent instead of it for `info=1`.
+/// Anything that isn't known is left out
+#[derive(Serialize)]
+pub struct InfoResponse {
+    /// The upload's saved name
+    name: String,
+
+    /// Length of the upload (in bytes)
+    size: u64,
+
+    /// The content type it's served with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<&'static str>,
+
+    /// When it was created (RFC 3339)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created: Option<String>,
+
+    /// When it expires, for temporary uploads (RFC 3339)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires: Option<String>,
+
+    /// Whether it's in the cache right now
+    cached: bool,
+}
 
 /// Responses for a failed view operation
 pub enum ViewError {
     /// Will send status code 404 with a plaintext "not found" message.
     NotFound,
 
+    /// Will send status code 410 with a plaintext "expired" message.
+    Gone,
+
     /// Will send status code 500 with a plaintext "internal server error" message.
     InternalServerError,
 
-    /// Sends status code 416 with a plaintext "range not satisfiable" message.
-    RangeNotSatisfiable,
+    /// Sends status code 416 with a plaintext "range not satisfiable" message,
+    /// and a `Content-Range` header if the upload's length is known.
+    RangeNotSatisfiable(Option<u64>),
+
+    /// Sends status code 429 with a plaintext "too many downloads" message.
+    TooManyDownloads,
+
+    /// Sends status code 403 with a plaintext "hotlinking not allowed" message.
+    Hotlinked,
 }
 
 impl IntoResponse for ViewError {
@@ -31,50 +112,314 @@ impl IntoResponse for ViewError {
         match self {
             ViewError::NotFound => (StatusCode::NOT_FOUND, "Not found!").into_response(),
 
+            ViewError::Gone => (StatusCode::GONE, "Upload has expired!").into_response(),
+
             ViewError::InternalServerError => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error!").into_response()
             }
 
-            ViewError::RangeNotSatisfiable => {
-                (StatusCode::RANGE_NOT_SATISFIABLE, "Range not satisfiable!").into_response()
+            ViewError::RangeNotSatisfiable(full_len) => {
+                let mut res =
+                    (StatusCode::RANGE_NOT_SATISFIABLE, "Range not satisfiable!").into_response();
+                if let Some(full_len) = full_len {
+                    let content_range = HeaderValue::from_str(&format!("bytes */{full_len}"))
+                        .expect("construct content-range header failed");
+                    res.headers_mut().insert("Content-Range", content_range);
+                }
+                res
             }
+
+            ViewError::TooManyDownloads => {
+                (StatusCode::TOO_MANY_REQUESTS, "Too many downloads!").into_response()
+            }
+
+            ViewError::Hotlinked => {
+                (StatusCode::FORBIDDEN, "Hotlinking is not allowed!").into_response()
+            }
+        }
+    }
+}
+
+/// Format a time as an RFC 3339 timestamp in UTC, like `2024-01-31T12:00:00Z`
+pub fn fmt_rfc3339(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let (days, rem) = (secs / 86400, secs % 86400);
+    let (hour, min, sec) = (rem / 3600, rem % 3600 / 60, rem % 60);
+
+    // convert days since the epoch to a civil date
+    // see: https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{min:02}:{sec:02}Z")
+}
+
+/// Parse an RFC 3339 timestamp, like `2024-01-31T12:00:00Z` or `2024-01-31T14:00:00.5+02:00`
+///
+/// Returns: `None` if it isn't one, or it's before the unix epoch
+pub fn parse_rfc3339(s: &str) -> Option<SystemTime> {
+    let num = |range: std::ops::Range<usize>| {
+        s.get(range)
+            .filter(|n| n.bytes().all(|b| b.is_ascii_digit()))?
+            .parse::<i64>()
+            .ok()
+    };
+    let sep = |i: usize, seps: &[u8]| s.as_bytes().get(i).is_some_and(|c| seps.contains(c));
+
+    // date and time
+    if !sep(4, b"-") || !sep(7, b"-") || !sep(10, b"Tt ") || !sep(13, b":") || !sep(16, b":") {
+        return None;
+    }
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    let (hour, min, sec) = (num(11..13)?, num(14..16)?, num(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || min > 59 || sec > 60 {
+        return None;
+    }
+
+    // skip fractional seconds
+    let mut rest = &s[19..];
+    if let Some(frac) = rest.strip_prefix('.') {
+        let digits = frac.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return None;
+        }
+        rest = &frac[digits..];
+    }
+
+    // offset from utc, in seconds
+    let offset = match rest.as_bytes() {
+        [b'Z' | b'z'] => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let (h, m) = (num(s.len() - 5..s.len() - 3)?, num(s.len() - 2..s.len())?);
+            let offset = h * 3600 + m * 60;
+            if *sign == b'+' { offset } else { -offset }
+        }
+        _ => return None,
+    };
+
+    // convert a civil date to days since the epoch
+    // see: https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let y = year - i64::from(month <= 2);
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let secs = days * 86400 + hour * 3600 + min * 60 + sec - offset;
+    Some(UNIX_EPOCH + Duration::from_secs(secs.try_into().ok()?))
+}
+
+/// How many chunks of a `multipart/byteranges` body streamed from disk
+/// can be read ahead of the client
+const DISK_PARTS_BACKLOG: usize = 4;
+
+/// The framing around each part of a `multipart/byteranges` body
+struct Multipart {
+    boundary: String,
+    /// Boundary and headers sent before each part
+    heads: Vec<Bytes>,
+    /// Closing boundary, sent after the last part
+    tail: Bytes,
+    /// Length of the whole body, parts included
+    len: u64,
+}
+
+impl Multipart {
+    fn new(parts: &[(u64, u64)], content_type: &str, full_len: u64) -> Self {
+        let boundary = Alphanumeric.sample_string(&mut rand::rng(), 24);
+
+        // the end of a content-range is inclusive, unlike ours.
+        // parts are never empty, so this can't underflow
+        let heads: Vec<Bytes> = parts
+            .iter()
+            .map(|(start, end)| {
+                Bytes::from(format!(
+                    "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {start}-{}/{full_len}\r\n\r\n",
+                    end - 1
+                ))
+            })
+            .collect();
+        let tail = Bytes::from(format!("\r\n--{boundary}--\r\n"));
+
+        let framing: u64 = heads.iter().map(|h| h.len() as u64).sum();
+        let data: u64 = parts.iter().map(|(start, end)| end - start).sum();
+        let len = framing + data + tail.len() as u64;
+
+        Self {
+            boundary,
+            heads,
+            tail,
+            len,
         }
     }
 }
 
+/// Stream ranges of an upload from disk as a `multipart/byteranges` body.
+///
+/// Each range is read by seeking to it and taking its length from the file,
+/// so only a few chunks are ever held in memory, however big the ranges are
+fn stream_disk_parts(
+    saved_name: String,
+    mut file: File,
+    parts: Box<[(u64, u64)]>,
+    multipart: Multipart,
+    permit: Option<OwnedSemaphorePermit>,
+    read_buffer: usize,
+) -> Body {
+    let (tx, rx) = mpsc::channel(DISK_PARTS_BACKLOG);
+
+    // if the client goes away, sending fails and the file is let go of
+    tokio::spawn(async move {
+        let _permit = permit;
+
+        for (head, (start, end)) in multipart.heads.into_iter().zip(parts) {
+            if tx.send(Ok(head)).await.is_err() {
+                return;
+            }
+
+            if let Err(err) = file.seek(SeekFrom::Start(start)).await {
+                error!(%saved_name, %err, "failed to seek upload on disk mid-stream");
+                let _ = tx.send(Err(err)).await;
+                return;
+            }
+
+            // read errors are passed on so the connection is aborted,
+            // like when a single range is streamed
+            let mut reader =
+                ReaderStream::with_capacity((&mut file).take(end - start), read_buffer);
+            while let Some(res) = reader.next().await {
+                let failed = res.is_err();
+                if let Err(ref err) = res {
+                    error!(%saved_name, %err, "failed to read upload from disk mid-stream");
+                }
+                if tx.send(res).await.is_err() || failed {
+                    return;
+                }
+            }
+        }
+
+        let _ = tx.send(Ok(multipart.tail)).await;
+    });
+
+    Body::from_stream(ReceiverStream::new(rx))
+}
+
 impl IntoResponse for UploadResponse {
     fn into_response(self) -> Response {
         let (start, end) = self.range;
-        let range_len = end - start;
+        let mut content_length = end - start;
+        let media_type = mime::media_type_for(&self.saved_name);
+
+        // framing for several ranges, which each say what they are
+        let part_type = media_type.unwrap_or("application/octet-stream");
+        let mut boundary = None;
 
         let mut res = match self.data {
             UploadData::Cache(data) => data.into_response(),
-            UploadData::Disk(file) => {
-                let reader_stream = ReaderStream::new(file);
-                let body = Body::from_stream(reader_stream);
-                let mut res = body.into_response();
-                let headers = res.headers_mut();
+            UploadData::CacheParts(data, parts) => {
+                let multipart = Multipart::new(&parts, part_type, self.full_len);
+                content_length = multipart.len;
 
-                // add Content-Length header so the browser shows how big a file is when it's being downloaded
-                let content_length = HeaderValue::from_str(&range_len.to_string())
-                    .expect("construct content-length header failed");
-                headers.insert("Content-Length", content_length);
+                // slicing only bumps a refcount, so no part is copied.
+                // these fit in a usize, because the whole upload does
+                let mut chunks = Vec::with_capacity(parts.len() * 2 + 1);
+                for (head, (start, end)) in multipart.heads.into_iter().zip(parts) {
+                    chunks.push(head);
+                    chunks.push(data.slice(start as usize..end as usize));
+                }
+                chunks.push(multipart.tail);
 
-                res
+                boundary = Some(multipart.boundary);
+                let chunks = chunks.into_iter().map(Ok::<_, std::convert::Infallible>);
+                Body::from_stream(tokio_stream::iter(chunks)).into_response()
+            }
+            UploadData::DiskParts(file, parts, permit) => {
+                let multipart = Multipart::new(&parts, part_type, self.full_len);
+                content_length = multipart.len;
+                boundary = Some(multipart.boundary.clone());
+
+                let saved_name = self.saved_name;
+                stream_disk_parts(saved_name, file, parts, multipart, permit, self.read_buffer)
+                    .into_response()
+            }
+            UploadData::Disk(file, permit) => {
+                // log read errors instead of letting them pass silently.
+                // the error is still passed on, so the connection is aborted
+                // instead of the client being sent a truncated file.
+                // the read permit goes with the stream, so it's released once it's dropped
+                let saved_name = self.saved_name;
+                let reader_stream =
+                    ReaderStream::with_capacity(file, self.read_buffer).map(move |res| {
+                        let _ = &permit;
+                        if let Err(ref err) = res {
+                            error!(%saved_name, %err, "failed to read upload from disk mid-stream");
+                        }
+                        res
+                    });
+                let body = Body::from_stream(reader_stream);
+                body.into_response()
             }
         };
 
         let headers = res.headers_mut();
 
+        // add Content-Length header so the browser shows how big a file is when it's being downloaded.
+        // it's set for cache responses too, so it always matches the range being sent
+        let content_length = HeaderValue::from_str(&content_length.to_string())
+            .expect("construct content-length header failed");
+        headers.insert("Content-Length", content_length);
+
         // remove content-type, browser can imply content type
+        // (except for media, which won't play properly without it)
         headers.remove("Content-Type");
-        headers.insert("Accept-Ranges", HeaderValue::from_static("bytes"));
-        // ^-- indicate that byte ranges are supported. maybe unneeded, but probably good
+        if let Some(content_type) = media_type {
+            headers.insert("Content-Type", HeaderValue::from_static(content_type));
+        }
+        // indicate whether byte ranges are supported, so download managers
+        // don't try to resume responses that can't be
+        let accept_ranges = if self.ranges { "bytes" } else { "none" };
+        headers.insert("Accept-Ranges", HeaderValue::from_static(accept_ranges));
+
+        // show whether the upload came from the cache, for tuning it
+        let cache_status = if self.cache_hit { "HIT" } else { "MISS" };
+        headers.insert("X-Cache", HeaderValue::from_static(cache_status));
+
+        // let clients know when the upload was created
+        if let Some(created) = self.created {
+            let upload_time = HeaderValue::from_str(&fmt_rfc3339(created))
+                .expect("construct upload time header failed");
+            headers.insert("X-Upload-Time", upload_time);
+        }
+
+        // several ranges are sent as parts, which have their own content-range
+        if let Some(boundary) = boundary {
+            let content_type =
+                HeaderValue::from_str(&format!("multipart/byteranges; boundary={boundary}"))
+                    .expect("construct multipart content-type header failed");
 
-        // if it is not the full size, add relevant headers/status for range request
-        if range_len != self.full_len {
+            headers.insert("Content-Type", content_type);
+            *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+        }
+        // if a range was asked for, add relevant headers/status for range request.
+        // this is still a 206 if the range happens to cover the whole upload
+        else if self.partial && self.ranges {
+            // the end of a content-range is inclusive, unlike ours.
+            // partial ranges are never empty, so this can't underflow
             let content_range =
-                HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, self.full_len))
+                HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end - 1, self.full_len))
                     .expect("construct content-range header failed");
 
             headers.insert("Content-Range", content_range);
@@ -85,30 +430,499 @@ impl IntoResponse for UploadResponse {
     }
 }
 
+/// Get the host out of a URL (or a bare host), lowercased and without a port
+fn url_host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+
+    // IPv6 addresses keep their brackets, and their colons
+    match host.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    }
+}
+
+/// Whether a view with this `Referer` is allowed, given the hosts that can
+/// embed uploads. The instance itself (`own_url`) always can.
+fn referrer_allowed(
+    referer: Option<&HeaderValue>,
+    allowed: &[String],
+    allow_none: bool,
+    own_url: &str,
+) -> bool {
+    if allowed.is_empty() {
+        return true;
+    }
+
+    let referer = match referer.map(HeaderValue::to_str) {
+        Some(Ok(r)) if !r.trim().is_empty() => r.trim(),
+        // unreadable referrers can't be checked, so they're never allowed
+        Some(Err(_)) => return false,
+        _ => return allow_none,
+    };
+    let host = url_host(referer).to_ascii_lowercase();
+
+    if host == url_host(own_url).to_ascii_lowercase() {
+        return true;
+    }
+
+    allowed.iter().any(|entry| {
+        let entry = entry.trim().to_ascii_lowercase();
+        match entry.strip_prefix("*.") {
+            Some(parent) => host
+                .strip_suffix(url_host(parent))
+                .is_some_and(|sub| sub.ends_with('.')),
+            None => host == url_host(&entry),
+        }
+    })
+}
+
+/// Build a `Content-Disposition` header that keeps an upload inline,
+/// but makes browsers save it under the name given.
+///
+/// Returns: `None` if the name isn't one a file could sensibly have
+fn content_disposition(file_name: &str) -> Option<HeaderValue> {
+    if file_name.is_empty()
+        || file_name.len() > 255
+        || file_name.chars().any(char::is_control)
+        || file_name == "."
+        || file_name == ".."
+    {
+        return None;
+    }
+
+    // plain names can just be quoted,
+    // anything else has to be percent-encoded
+    let plain = file_name
+        .bytes()
+        .all(|b| (b.is_ascii_graphic() || b == b' ') && b != b'"' && b != b'\\');
+    let value = if plain {
+        format!("inline; filename=\"{file_name}\"")
+    } else {
+        let encoded: String = file_name
+            .bytes()
+            .map(|b| {
+                if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                    (b as char).to_string()
+                } else {
+                    format!("%{b:02X}")
+                }
+            })
+            .collect();
+        format!("inline; filename*=UTF-8''{encoded}")
+    };
+
+    HeaderValue::from_str(&value).ok()
+}
+
 /// GET request handler for /p/* path.
 /// All file views are handled here.
 pub async fn view(
     State(engine): State<Arc<Engine>>,
-    Path(original_path): Path<PathBuf>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(path): Path<ViewPath>,
+    Query(req): Query<ViewRequest>,
     range: Option<TypedHeader<Range>>,
-) -> Result<UploadResponse, ViewError> {
+    headers: HeaderMap,
+) -> Result<Response, ViewError> {
     // try to extract the file name (if it's the only component)
     // this makes paths like `asdf%2fabcdef.png` invalid
+    let original_path = path.saved_name;
     let saved_name = match original_path.file_name().map(OsStr::to_str) {
         Some(Some(n)) if original_path.components().count() == 1 => n,
-        _ => return Err(ViewError::NotFound),
+        _ => {
+            engine.not_found_jitter().await;
+            return Err(ViewError::NotFound);
+        }
+    };
+
+    // other sites can only embed uploads if they're allowed to.
+    // without a base URL, the instance is wherever the client reached it
+    let own_url = match engine.cfg.base_url.as_str() {
+        "" => headers
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default(),
+        base_url => base_url,
     };
+    if !referrer_allowed(
+        headers.get(header::REFERER),
+        &engine.allowed_referrers,
+        engine.allow_no_referrer,
+        own_url,
+    ) {
+        return Err(ViewError::Hotlinked);
+    }
+
+    // a trailing file name is only used for saving the upload,
+    // but it still has to be one a file could have
+    let disposition = match path.file_name.as_deref().map(content_disposition) {
+        Some(None) => {
+            engine.not_found_jitter().await;
+            return Err(ViewError::NotFound);
+        }
+        Some(Some(disposition)) => Some(disposition),
+        None => None,
+    };
+
+    // metadata isn't a download, so it skips the download limit
+    if req.is_info() {
+        return match engine.info(saved_name).await {
+            Ok(Some(info)) => Ok(Json(InfoResponse {
+                name: saved_name.to_string(),
+                size: info.length,
+                content_type: info.content_type,
+                created: info.created.map(fmt_rfc3339),
+                expires: info.expires.map(fmt_rfc3339),
+                cached: info.cached,
+            })
+            .into_response()),
+            Ok(None) => {
+                engine.not_found_jitter().await;
+                Err(ViewError::NotFound)
+            }
+            Err(err) => {
+                error!("failed to get upload info!! {err:#}");
+                Err(ViewError::InternalServerError)
+            }
+        };
+    }
+
+    // take a download slot for this client (if they're limited).
+    // it's held until the response body is done being sent
+    let permit = match engine.downloads.as_ref().map(|l| l.acquire(addr.ip())) {
+        Some(Some(permit)) => Some(permit),
+        Some(None) => return Err(ViewError::TooManyDownloads),
+        None => None,
+    };
+    let hold = move |res: Response| match permit {
+        Some(permit) => res.map(|body| {
+            Body::from_stream(body.into_data_stream().map(move |chunk| {
+                let _ = &permit;
+                chunk
+            }))
+        }),
+        None => res,
+    };
+
+    // following streams appends to a paste as they happen
+    if req
+        .follow
+        .as_deref()
+        .is_some_and(|f| f == "1" || f == "true")
+    {
+        return match engine.follow(saved_name).await {
+            Ok(Some(stream)) => {
+                let mut res = Body::from_stream(stream).into_response();
+                res.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static("text/plain; charset=utf-8"),
+                );
+                Ok(hold(res))
+            }
+            Ok(None) => {
+                engine.not_found_jitter().await;
+                Err(ViewError::NotFound)
+            }
+            Err(err) => {
+                error!("failed to follow upload!! {err:#}");
+                Err(ViewError::InternalServerError)
+            }
+        };
+    }
 
     let range = range.map(|TypedHeader(range)| range);
 
     // get result from the engine
     match engine.get(saved_name, range).await {
-        Ok(GetOutcome::Success(res)) => Ok(res),
-        Ok(GetOutcome::NotFound) => Err(ViewError::NotFound),
-        Ok(GetOutcome::RangeNotSatisfiable) => Err(ViewError::RangeNotSatisfiable),
+        Ok(GetOutcome::Success(res)) => {
+            let mut res = (*res).into_response();
+            if let Some(disposition) = disposition {
+                res.headers_mut()
+                    .insert(header::CONTENT_DISPOSITION, disposition);
+            }
+            Ok(hold(res))
+        }
+        Ok(GetOutcome::NotFound) => {
+            engine.not_found_jitter().await;
+            Err(ViewError::NotFound)
+        }
+        Ok(GetOutcome::Gone) => Err(ViewError::Gone),
+        Ok(GetOutcome::RangeNotSatisfiable(full_len)) => {
+            Err(ViewError::RangeNotSatisfiable(full_len))
+        }
         Err(err) => {
             error!("failed to get upload!! {err:#}");
             Err(ViewError::InternalServerError)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use axum::response::IntoResponse;
+    use bytes::Bytes;
+    use http::StatusCode;
+
+    use super::{ViewError, content_disposition, fmt_rfc3339, parse_rfc3339, referrer_allowed};
+    use crate::engine::{UploadData, UploadResponse};
+
+    /// Make sure unsatisfiable ranges say how long the upload is,
+    /// when that's known
+    #[test]
+    fn unsatisfiable_range() {
+        let res = ViewError::RangeNotSatisfiable(Some(10)).into_response();
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers()["Content-Range"], "bytes */10");
+
+        let res = ViewError::RangeNotSatisfiable(None).into_response();
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert!(!res.headers().contains_key("Content-Range"));
+    }
+
+    /// Make sure a range of a cached upload is sent
+    /// with headers that match the range
+    #[test]
+    fn cache_range_headers() {
+        let data = Bytes::from_static(b"0123456789");
+        let res = UploadResponse {
+            saved_name: "abcdef.txt".to_string(),
+            created: None,
+            cache_hit: true,
+            full_len: 10,
+            range: (2, 5),
+            partial: true,
+            ranges: true,
+            read_buffer: 4096,
+            data: UploadData::Cache(data.slice(2..5)),
+        }
+        .into_response();
+
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()["Content-Length"], "3");
+        assert_eq!(res.headers()["Content-Range"], "bytes 2-4/10");
+        assert_eq!(res.headers()["X-Cache"], "HIT");
+    }
+
+    /// Make sure a range covering the whole upload is still a 206,
+    /// and only a request without one gets a 200
+    #[test]
+    fn whole_range_status() {
+        let respond = |partial| {
+            UploadResponse {
+                saved_name: "abcdef.txt".to_string(),
+                created: None,
+                cache_hit: true,
+                full_len: 10,
+                range: (0, 10),
+                partial,
+                ranges: true,
+                read_buffer: 4096,
+                data: UploadData::Cache(Bytes::from_static(b"0123456789")),
+            }
+            .into_response()
+        };
+
+        let res = respond(true);
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()["Content-Range"], "bytes 0-9/10");
+
+        let res = respond(false);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers().contains_key("Content-Range"));
+    }
+
+    /// Make sure responses that can't serve ranges don't advertise them
+    #[test]
+    fn no_ranges() {
+        let res = UploadResponse {
+            saved_name: "abcdef.txt".to_string(),
+            created: None,
+            cache_hit: true,
+            full_len: 10,
+            range: (0, 10),
+            partial: false,
+            ranges: false,
+            read_buffer: 4096,
+            data: UploadData::Cache(Bytes::from_static(b"0123456789")),
+        }
+        .into_response();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["Accept-Ranges"], "none");
+    }
+
+    /// The body of a response with several parts, with its boundary
+    /// swapped for a fixed one so it can be compared
+    async fn multipart_body(res: axum::response::Response) -> String {
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(!res.headers().contains_key("Content-Range"));
+
+        let content_type = res.headers()["Content-Type"].to_str().unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_string();
+        let content_length: usize = res.headers()["Content-Length"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.len(), content_length);
+
+        String::from_utf8(body.to_vec())
+            .unwrap()
+            .replace(&boundary, "BOUNDARY")
+    }
+
+    const PARTS_BODY: &str = "\r\n--BOUNDARY\r\nContent-Type: application/octet-stream\r\n\
+        Content-Range: bytes 6-8/10\r\n\r\n678\
+        \r\n--BOUNDARY\r\nContent-Type: application/octet-stream\r\n\
+        Content-Range: bytes 0-1/10\r\n\r\n01\
+        \r\n--BOUNDARY--\r\n";
+
+    /// Make sure several ranges of a cached upload
+    /// are framed properly as parts
+    #[tokio::test]
+    async fn cache_parts() {
+        let res = UploadResponse {
+            saved_name: "abcdef.bin".to_string(),
+            created: None,
+            cache_hit: true,
+            full_len: 10,
+            range: (0, 10),
+            partial: true,
+            ranges: true,
+            read_buffer: 4096,
+            data: UploadData::CacheParts(
+                Bytes::from_static(b"0123456789"),
+                Box::new([(6, 9), (0, 2)]),
+            ),
+        }
+        .into_response();
+
+        assert_eq!(multipart_body(res).await, PARTS_BODY);
+    }
+
+    /// Make sure several ranges of an upload on disk are streamed
+    /// the same way they would be from the cache
+    #[tokio::test]
+    async fn disk_parts() {
+        let path = std::env::temp_dir().join(format!("breeze-parts-test-{}", std::process::id()));
+        tokio::fs::write(&path, b"0123456789").await.unwrap();
+        let file = tokio::fs::File::open(&path).await.unwrap();
+
+        let res = UploadResponse {
+            saved_name: "abcdef.bin".to_string(),
+            created: None,
+            cache_hit: false,
+            full_len: 10,
+            range: (0, 10),
+            partial: true,
+            ranges: true,
+            // small enough that parts take a few reads
+            read_buffer: 2,
+            data: UploadData::DiskParts(file, Box::new([(6, 9), (0, 2)]), None),
+        }
+        .into_response();
+
+        assert_eq!(multipart_body(res).await, PARTS_BODY);
+        tokio::fs::remove_file(&path).await.unwrap();
+    }
+
+    /// Make sure timestamps are formatted properly,
+    /// including around leap days
+    #[test]
+    fn rfc3339() {
+        assert_eq!(fmt_rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+
+        let at = |secs| fmt_rfc3339(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1_709_251_199), "2024-02-29T23:59:59Z");
+        assert_eq!(at(1_735_689_600), "2025-01-01T00:00:00Z");
+    }
+
+    /// Make sure timestamps are parsed properly,
+    /// with offsets and fractional seconds
+    #[test]
+    fn parse_timestamps() {
+        let at = |secs| Some(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), at(0));
+        assert_eq!(parse_rfc3339("2000-02-29T00:00:00Z"), at(951_782_400));
+        assert_eq!(parse_rfc3339("2024-02-29T23:59:59.999Z"), at(1_709_251_199));
+        assert_eq!(
+            parse_rfc3339("2025-01-01T02:00:00+02:00"),
+            at(1_735_689_600)
+        );
+        assert_eq!(
+            parse_rfc3339("2024-12-31t19:30:00-04:30"),
+            at(1_735_689_600)
+        );
+
+        // not timestamps, or before the epoch
+        assert_eq!(parse_rfc3339("2025-01-01"), None);
+        assert_eq!(parse_rfc3339("2025-13-01T00:00:00Z"), None);
+        assert_eq!(parse_rfc3339("2025-01-01T00:00:00"), None);
+        assert_eq!(parse_rfc3339("1969-12-31T23:59:59Z"), None);
+    }
+
+    /// Make sure trailing file names are quoted or encoded properly,
+    /// and ones no file could have are refused
+    #[test]
+    fn disposition() {
+        let value = |name| content_disposition(name).unwrap();
+
+        assert_eq!(value("vacation.png"), "inline; filename=\"vacation.png\"");
+        assert_eq!(value("my photo.png"), "inline; filename=\"my photo.png\"");
+        assert_eq!(
+            value("caf\u{e9} \"1\".png"),
+            "inline; filename*=UTF-8''caf%C3%A9%20%221%22.png"
+        );
+
+        assert!(content_disposition("").is_none());
+        assert!(content_disposition("..").is_none());
+        assert!(content_disposition("bad\nname.png").is_none());
+        assert!(content_disposition(&"a".repeat(256)).is_none());
+    }
+
+    /// Make sure only allowed sites (and the instance itself) can embed uploads
+    #[test]
+    fn referrers() {
+        let allowed = vec!["example.com".to_string(), "*.friends.net".to_string()];
+        let check = |referer: Option<&'static str>, allow_none| {
+            let referer = referer.map(http::HeaderValue::from_static);
+            referrer_allowed(
+                referer.as_ref(),
+                &allowed,
+                allow_none,
+                "https://picture.wtf",
+            )
+        };
+
+        assert!(check(Some("https://example.com/page"), true));
+        assert!(check(Some("http://EXAMPLE.com:8080/"), true));
+        assert!(check(Some("https://cdn.friends.net/a?b"), true));
+        assert!(check(Some("https://picture.wtf/"), true));
+
+        assert!(!check(Some("https://example.com.evil.com/"), true));
+        assert!(!check(Some("https://notexample.com/"), true));
+        assert!(!check(Some("https://friends.net/"), true));
+        assert!(!check(Some("https://evilfriends.net/"), true));
+
+        // no referrer at all
+        assert!(check(None, true));
+        assert!(!check(None, false));
+
+        // nothing is checked without a list
+        assert!(referrer_allowed(None, &[], false, "https://picture.wtf"));
+    }
+}